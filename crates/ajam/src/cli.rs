@@ -12,11 +12,84 @@ pub(crate) enum Command {
     /// Help message for write.
     Stop,
     Run {
-        /// The profile to run
+        /// The profile to run, the profiles directory of the config if omitted
         #[clap(short, long)]
-        profiles: String,
+        profiles: Option<String>,
+
+        /// Use an in-memory deck of the given device type instead of hardware
+        #[clap(long, value_name = "DEVICE")]
+        virtual_deck: Option<String>,
+
+        /// Path of the control socket
+        #[clap(long)]
+        socket: Option<String>,
+
+        /// Connect only to this device, a serial number or device type. Overrides the config
+        #[clap(long, value_name = "SERIAL|DEVICE")]
+        device: Vec<String>,
     },
     Status,
+    /// List connected devices
+    Devices,
+    /// Validate profiles and report problems
+    Check {
+        /// The directory containing the profiles
+        #[clap(short, long)]
+        profiles: Option<String>,
+    },
+    /// Control a running daemon
+    Ctl {
+        /// Path of the control socket
+        #[clap(long)]
+        socket: Option<String>,
+
+        /// Serial number of the deck to control, the first deck if omitted
+        #[clap(long)]
+        deck: Option<String>,
+
+        #[clap(subcommand)]
+        command: CtlCommand,
+    },
+    /// Inspect the configuration
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum ConfigCommand {
+    /// Print the configuration in effect, with defaults filled in
+    Show,
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum CtlCommand {
+    /// Print the current profile and page
+    Status,
+    /// Navigate to a page
+    Navigate {
+        /// The page to open
+        page: String,
+
+        /// The profile containing the page, current profile if omitted
+        #[clap(short, long)]
+        profile: Option<String>,
+    },
+    /// Set the display brightness
+    Brightness {
+        /// Brightness from 0 to 100
+        value: u8,
+    },
+    /// Trigger the action of a button on the current page
+    Press {
+        /// The button index
+        key: u8,
+    },
+    /// Reload profiles from disk
+    Reload,
+    /// Print the device connection state
+    Connection,
 }
 
 /// Utility to add ticket id to commit message
@@ -31,6 +104,10 @@ pub(crate) struct Cli {
     #[arg(long)]
     pub no_color: bool,
 
+    /// Path of the config file, `config.yaml` in the app directory if omitted
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<String>,
+
     #[clap(subcommand)]
     pub command: Command,
 }
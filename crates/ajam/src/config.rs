@@ -0,0 +1,290 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use ajam_profile::parse_kind;
+use ajazz_sdk::info::Kind;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::control::SOCKET_FILE_NAME;
+
+pub const CONFIG_FILE_NAME: &str = "config.yaml";
+
+/// Returns the directory of the daemon files, private to the user.
+pub(crate) fn app_dir() -> PathBuf {
+    let home = std::env::var_os("HOME").map(PathBuf::from).unwrap_or_else(std::env::temp_dir);
+    home.join("Library/Application Support/ajam")
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("failed to read config: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("invalid config: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+
+    #[error("invalid config: {0} {1}")]
+    Invalid(&'static str, String),
+}
+
+/// Config is the global configuration of the daemon, read from `config.yaml`.
+///
+/// Every field is optional, command line flags override it.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Config {
+    /// Profiles is the profiles directory, `profiles` in the app directory if unset.
+    pub profiles: Option<PathBuf>,
+    /// Socket is the path of the control socket, `ajam.sock` in the app directory by default.
+    pub socket: PathBuf,
+    /// Devices are the decks to connect to, by serial number or device type.
+    /// Every deck is connected if empty.
+    pub devices: Vec<String>,
+    pub agent: AgentConfig,
+    pub defaults: DefaultsConfig,
+    pub brightness: BrightnessConfig,
+    /// ImageCacheSize is how many decoded images are kept in memory.
+    pub image_cache_size: usize,
+    pub connection: ConnectionConfig,
+}
+
+/// AgentConfig is the launch agent installed by `ajam start`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct AgentConfig {
+    pub label: String,
+    pub stdout: PathBuf,
+    pub stderr: PathBuf,
+}
+
+/// DefaultsConfig is the profile shown when no other one applies and the page profiles open on.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct DefaultsConfig {
+    pub profile: String,
+    pub page: String,
+}
+
+/// BrightnessConfig is the display brightness on start and how much an encoder tick changes it.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct BrightnessConfig {
+    pub initial: u8,
+    pub step: u8,
+}
+
+/// ConnectionConfig is how often devices are searched for besides device notifications.
+/// A device failing to connect is retried from the minimum interval, doubling up to the
+/// maximum, which is also the interval of the fallback search.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ConnectionConfig {
+    pub min_scan_interval_ms: u64,
+    pub max_scan_interval_ms: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            profiles: None,
+            socket: app_dir().join(SOCKET_FILE_NAME),
+            devices: Vec::new(),
+            agent: AgentConfig::default(),
+            defaults: DefaultsConfig::default(),
+            brightness: BrightnessConfig::default(),
+            image_cache_size: 120,
+            connection: ConnectionConfig::default(),
+        }
+    }
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        Self {
+            label: "co.myrt.ajam".to_string(),
+            stdout: PathBuf::from("/tmp/ajam.out"),
+            stderr: PathBuf::from("/tmp/ajam.err"),
+        }
+    }
+}
+
+impl Default for DefaultsConfig {
+    fn default() -> Self {
+        Self {
+            profile: "common".to_string(),
+            page: "main".to_string(),
+        }
+    }
+}
+
+impl Default for BrightnessConfig {
+    fn default() -> Self {
+        Self {
+            initial: 100,
+            step: 5,
+        }
+    }
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self {
+            min_scan_interval_ms: 250,
+            max_scan_interval_ms: 5000,
+        }
+    }
+}
+
+impl Config {
+    /// Reads and validates the config file, a missing file is the default config.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let data = match fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let config: Config = serde_yaml::from_str(&data)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |field, message: &str| Err(ConfigError::Invalid(field, message.to_string()));
+        if self.agent.label.is_empty() {
+            return invalid("agent.label", "must not be empty");
+        }
+        if self.defaults.profile.is_empty() {
+            return invalid("defaults.profile", "must not be empty");
+        }
+        if self.defaults.page.is_empty() {
+            return invalid("defaults.page", "must not be empty");
+        }
+        if self.brightness.initial > 100 {
+            return invalid("brightness.initial", "must be at most 100");
+        }
+        if !(1..=100).contains(&self.brightness.step) {
+            return invalid("brightness.step", "must be from 1 to 100");
+        }
+        if self.image_cache_size == 0 {
+            return invalid("image_cache_size", "must be positive");
+        }
+        if self.connection.min_scan_interval_ms == 0 {
+            return invalid("connection.min_scan_interval_ms", "must be positive");
+        }
+        if self.connection.max_scan_interval_ms < self.connection.min_scan_interval_ms {
+            return invalid(
+                "connection.max_scan_interval_ms",
+                "must not be less than connection.min_scan_interval_ms",
+            );
+        }
+        Ok(())
+    }
+}
+
+impl ConnectionConfig {
+    pub fn min_scan_interval(&self) -> Duration {
+        Duration::from_millis(self.min_scan_interval_ms)
+    }
+
+    pub fn max_scan_interval(&self) -> Duration {
+        Duration::from_millis(self.max_scan_interval_ms)
+    }
+}
+
+/// DeviceFilter selects the decks to connect to.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DeviceFilter {
+    selectors: Vec<DeviceSelector>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum DeviceSelector {
+    Kind(Kind),
+    Serial(String),
+}
+
+impl DeviceFilter {
+    /// Creates a filter from device types and serial numbers, an empty list selects every deck.
+    pub fn new(devices: &[String]) -> Self {
+        let selectors = devices
+            .iter()
+            .map(|device| match parse_kind(device) {
+                Some(kind) => DeviceSelector::Kind(kind),
+                None => DeviceSelector::Serial(device.clone()),
+            })
+            .collect();
+        Self { selectors }
+    }
+
+    pub fn matches(&self, kind: Kind, serial: &str) -> bool {
+        self.selectors.is_empty()
+            || self.selectors.iter().any(|selector| match selector {
+                DeviceSelector::Kind(selected) => *selected == kind,
+                DeviceSelector::Serial(selected) => selected == serial,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::testing::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_device_filter() {
+        let filter = DeviceFilter::new(&[]);
+        assert!(filter.matches(Kind::Akp03, "A1B2C3"));
+
+        let filter = DeviceFilter::new(&["akp153".to_string(), "A1B2C3".to_string()]);
+        assert!(filter.matches(Kind::Akp153, "D4E5F6"));
+        assert!(filter.matches(Kind::Akp03, "A1B2C3"));
+        assert!(!filter.matches(Kind::Akp03, "D4E5F6"));
+    }
+
+    #[test]
+    fn test_load_config() {
+        let dir = TempDir::new();
+        let path = dir.path().join("config.yaml");
+        assert!(Config::load(&path).unwrap().devices.is_empty());
+
+        fs::write(&path, "devices:\n  - A1B2C3\n").unwrap();
+        let config = Config::load(&path);
+        fs::write(&path, "device: A1B2C3\n").unwrap();
+        let invalid = Config::load(&path);
+
+        assert_eq!(config.unwrap().devices, vec!["A1B2C3"]);
+        assert!(matches!(invalid, Err(ConfigError::Yaml(_))));
+    }
+
+    #[test]
+    fn test_validate() {
+        let config = |source: &str| {
+            let config: Config = serde_yaml::from_str(source).unwrap();
+            config.validate().map(|_| config)
+        };
+
+        let partial = config("brightness:\n  step: 10\ndefaults:\n  profile: home\n").unwrap();
+        assert_eq!(partial.brightness.step, 10);
+        assert_eq!(partial.brightness.initial, 100);
+        assert_eq!(partial.defaults.profile, "home");
+        assert_eq!(partial.defaults.page, "main");
+
+        let error = config("brightness:\n  initial: 150\n").unwrap_err();
+        assert_eq!(error.to_string(), "invalid config: brightness.initial must be at most 100");
+        assert!(config("image_cache_size: 0\n").is_err());
+        assert!(config("connection:\n  min_scan_interval_ms: 1000\n  max_scan_interval_ms: 500\n").is_err());
+        assert!(serde_yaml::from_str::<Config>("brightness:\n  steps: 10\n").is_err());
+    }
+
+    #[test]
+    fn test_show_roundtrip() {
+        let shown = serde_yaml::to_string(&Config::default()).unwrap();
+        let config: Config = serde_yaml::from_str(&shown).unwrap();
+        assert_eq!(config.agent.label, "co.myrt.ajam");
+        assert_eq!(config.image_cache_size, 120);
+    }
+}
@@ -0,0 +1,38 @@
+use std::path::Path;
+
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+
+use super::{Call, ControlError, Request, Response};
+
+/// Sends a single request to a running daemon and returns its result.
+///
+/// `deck` is the serial number of the deck the call is for, the first deck if `None`.
+pub(crate) async fn call(
+    path: &Path,
+    deck: Option<&str>,
+    call: Call,
+) -> Result<Value, ControlError> {
+    let stream = UnixStream::connect(path).await?;
+    let (reader, mut writer) = stream.into_split();
+
+    let request = Request {
+        deck: deck.map(str::to_string),
+        ..Request::new(1, call)
+    };
+    let mut payload = serde_json::to_string(&request)?;
+    payload.push('\n');
+    writer.write_all(payload.as_bytes()).await?;
+
+    let mut lines = BufReader::new(reader).lines();
+    let Some(line) = lines.next_line().await? else {
+        return Err(ControlError::Closed);
+    };
+
+    let response: Response = serde_json::from_str(&line)?;
+    if let Some(error) = response.error {
+        return Err(ControlError::Remote(error.message));
+    }
+    Ok(response.result.unwrap_or(Value::Null))
+}
@@ -0,0 +1,49 @@
+mod client;
+mod protocol;
+mod server;
+
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use crate::state::{NavigationError, ReloadError, RenderError};
+
+pub(crate) use client::call;
+pub(crate) use protocol::{Call, ConnectionInfo, DeckInfo, Request, Response, StatusInfo};
+pub(crate) use server::{bind, serve};
+
+/// SOCKET_FILE_NAME is the name of the control socket in the app directory.
+pub const SOCKET_FILE_NAME: &str = "ajam.sock";
+
+#[derive(Error, Debug)]
+pub enum ControlError {
+    #[error("socket error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("invalid message: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("{0}")]
+    Navigation(#[from] NavigationError),
+
+    #[error("{0}")]
+    Render(#[from] RenderError),
+
+    #[error("{0}")]
+    Reload(#[from] ReloadError),
+
+    #[error("no button for key: {0}")]
+    NoButton(u8),
+
+    #[error("no deck with serial number: {0}")]
+    NoDeck(String),
+
+    #[error("another daemon is listening on {0}")]
+    AlreadyRunning(PathBuf),
+
+    #[error("daemon closed the connection")]
+    Closed,
+
+    #[error("{0}")]
+    Remote(String),
+}
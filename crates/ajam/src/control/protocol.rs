@@ -0,0 +1,165 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub(crate) const JSONRPC_VERSION: &str = "2.0";
+
+const PARSE_ERROR: i32 = -32700;
+const SERVER_ERROR: i32 = -32000;
+
+/// Request is a single JSON-RPC request, sent as one line over the socket.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Request {
+    pub jsonrpc: String,
+    pub id: u64,
+    /// Deck is the serial number of the deck the call is for, the first deck if omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deck: Option<String>,
+    #[serde(flatten)]
+    pub call: Call,
+}
+
+/// Call is a method supported by the control socket along with its params.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub(crate) enum Call {
+    Status,
+    Navigate {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        profile: Option<String>,
+        page: String,
+    },
+    SetBrightness {
+        brightness: u8,
+    },
+    Press {
+        key: u8,
+    },
+    Reload,
+    Connection,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Response {
+    pub jsonrpc: String,
+    pub id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct RpcError {
+    pub code: i32,
+    pub message: String,
+}
+
+/// StatusInfo is the result of the `status` method.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct StatusInfo {
+    pub profile: String,
+    pub page: String,
+    pub brightness: u8,
+    pub connected: bool,
+}
+
+/// ConnectionInfo is the result of the `connection` method.
+///
+/// `device` is the kind of the first deck, `decks` lists all of them.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct ConnectionInfo {
+    pub connected: bool,
+    pub device: Option<String>,
+    #[serde(default)]
+    pub decks: Vec<DeckInfo>,
+}
+
+/// DeckInfo is a connected deck.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct DeckInfo {
+    pub serial: String,
+    pub device: String,
+}
+
+impl Request {
+    pub fn new(id: u64, call: Call) -> Self {
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            id,
+            deck: None,
+            call,
+        }
+    }
+}
+
+impl Response {
+    pub fn result(id: u64, result: Value) -> Self {
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            id: Some(id),
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    pub fn error(id: Option<u64>, message: String) -> Self {
+        let code = if id.is_some() {
+            SERVER_ERROR
+        } else {
+            PARSE_ERROR
+        };
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            id,
+            result: None,
+            error: Some(RpcError { code, message }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        let request: Request = serde_json::from_str(
+            r#"{"jsonrpc":"2.0","id":3,"method":"navigate","params":{"page":"second"}}"#,
+        )
+        .unwrap();
+        assert_eq!(request.id, 3);
+        assert_eq!(
+            request.call,
+            Call::Navigate {
+                profile: None,
+                page: "second".to_string()
+            }
+        );
+
+        let request: Request =
+            serde_json::from_str(r#"{"jsonrpc":"2.0","id":4,"method":"status"}"#).unwrap();
+        assert_eq!(request.call, Call::Status);
+        assert_eq!(request.deck, None);
+
+        let request: Request = serde_json::from_str(
+            r#"{"jsonrpc":"2.0","id":5,"deck":"A1B2C3","method":"press","params":{"key":1}}"#,
+        )
+        .unwrap();
+        assert_eq!(request.deck.as_deref(), Some("A1B2C3"));
+        assert_eq!(request.call, Call::Press { key: 1 });
+    }
+
+    #[test]
+    fn test_request_roundtrip() {
+        let line = serde_json::to_string(&Request::new(1, Call::Press { key: 2 })).unwrap();
+        let request: Request = serde_json::from_str(&line).unwrap();
+        assert_eq!(request.call, Call::Press { key: 2 });
+    }
+
+    #[test]
+    fn test_unknown_method() {
+        let result =
+            serde_json::from_str::<Request>(r#"{"jsonrpc":"2.0","id":1,"method":"explode"}"#);
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,138 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use colored::Colorize;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::task;
+
+use crate::state::Decks;
+use crate::{print_debug, print_error, print_info};
+
+use super::{ControlError, Request, Response};
+
+/// Binds the control socket, only the current user can connect to it.
+///
+/// A socket left behind by a daemon that is gone is replaced,
+/// while a daemon still answering on it keeps it.
+pub(crate) async fn bind(path: &Path) -> Result<UnixListener, ControlError> {
+    if path.exists() {
+        if UnixStream::connect(path).await.is_ok() {
+            return Err(ControlError::AlreadyRunning(path.to_path_buf()));
+        }
+        fs::remove_file(path)?;
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    // Requests run commands as the daemon owner, other users must not send them.
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    print_info!("Listening for control requests on {}", path.display());
+    Ok(listener)
+}
+
+/// Listens for control requests on the socket until the process exits.
+pub(crate) async fn serve(decks: Decks, listener: UnixListener) -> Result<(), ControlError> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let decks = decks.clone();
+        task::spawn(async move {
+            if let Err(e) = handle_connection(decks, stream).await {
+                print_error!("Control connection error: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(decks: Decks, stream: UnixStream) -> Result<(), ControlError> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = handle_line(&decks, &line).await;
+        let mut payload = serde_json::to_string(&response)?;
+        payload.push('\n');
+        writer.write_all(payload.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+async fn handle_line(decks: &Decks, line: &str) -> Response {
+    let request: Request = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => return Response::error(None, e.to_string()),
+    };
+    print_debug!("Control request: {:?}", request.call);
+
+    match decks.handle_call(request.deck.as_deref(), request.call).await {
+        Ok(result) => Response::result(request.id, result),
+        Err(e) => Response::error(Some(request.id), e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::control::{call, Call, StatusInfo};
+    use crate::state::testing::{attached_state, TempDir};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_socket_roundtrip() {
+        let (state, _deck, dir) = attached_state().await;
+        let socket = dir.path().join("ajam.sock");
+
+        let decks = Decks::with_state(state).await;
+        let listener = bind(&socket).await.unwrap();
+        task::spawn(async move { serve(decks, listener).await });
+        let socket = &socket;
+
+        let navigate = Call::Navigate {
+            profile: None,
+            page: "second".to_string(),
+        };
+        call(socket, None, navigate).await.unwrap();
+
+        let status: StatusInfo =
+            serde_json::from_value(call(socket, Some("virtual"), Call::Status).await.unwrap()).unwrap();
+        assert_eq!(status.page, "second");
+        assert!(status.connected);
+
+        let missing = Call::Navigate {
+            profile: None,
+            page: "missing".to_string(),
+        };
+        assert!(matches!(
+            call(socket, None, missing).await,
+            Err(ControlError::Remote(_))
+        ));
+        assert!(matches!(
+            call(socket, Some("missing"), Call::Status).await,
+            Err(ControlError::Remote(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_bind() {
+        let dir = TempDir::new();
+        let socket = dir.path().join("daemon").join("ajam.sock");
+
+        // A socket nobody listens on is left behind by a daemon that is gone.
+        drop(bind(&socket).await.unwrap());
+        let listener = bind(&socket).await.unwrap();
+        let mode = fs::metadata(&socket).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        assert!(matches!(
+            bind(&socket).await,
+            Err(ControlError::AlreadyRunning(_))
+        ));
+        drop(listener);
+    }
+}
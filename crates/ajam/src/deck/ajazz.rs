@@ -0,0 +1,104 @@
+use std::sync::{Arc, Mutex};
+
+use ajazz_sdk::asynchronous::AsyncDeviceStateReader;
+use ajazz_sdk::info::Kind;
+use ajazz_sdk::{list_devices, refresh_device_list, AsyncAjazz, DeviceStateUpdate, HidApi};
+use async_trait::async_trait;
+use colored::Colorize;
+use image::DynamicImage;
+
+use crate::print_error;
+
+use super::{DeckBackend, DeckError, DeckReader, DeviceSource, SharedDeck};
+
+const READER_POLL_RATE: f32 = 100.0;
+
+/// AjazzDeck is a connected Ajazz device along with its serial number.
+pub(crate) struct AjazzDeck {
+    device: AsyncAjazz,
+    serial: String,
+}
+
+impl AjazzDeck {
+    pub fn new(device: AsyncAjazz, serial: String) -> Self {
+        Self { device, serial }
+    }
+}
+
+#[async_trait]
+impl DeckBackend for AjazzDeck {
+    fn kind(&self) -> Kind {
+        self.device.kind()
+    }
+
+    fn serial(&self) -> String {
+        self.serial.clone()
+    }
+
+    fn reader(&self) -> Arc<dyn DeckReader> {
+        self.device.get_reader()
+    }
+
+    async fn set_brightness(&self, brightness: u8) -> Result<(), DeckError> {
+        self.device.set_brightness(brightness).await?;
+        Ok(())
+    }
+
+    async fn set_button_image(&self, key: u8, image: DynamicImage) -> Result<(), DeckError> {
+        self.device.set_button_image(key, image).await?;
+        Ok(())
+    }
+
+    async fn clear_button_image(&self, key: u8) -> Result<(), DeckError> {
+        self.device.clear_button_image(key).await?;
+        Ok(())
+    }
+
+    async fn clear_all_button_images(&self) -> Result<(), DeckError> {
+        self.device.clear_all_button_images().await?;
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<(), DeckError> {
+        self.device.flush().await?;
+        Ok(())
+    }
+}
+
+/// HidDeviceSource finds Ajazz devices over HID.
+pub(crate) struct HidDeviceSource {
+    hid: Mutex<HidApi>,
+}
+
+impl HidDeviceSource {
+    pub fn new(hid: HidApi) -> Self {
+        Self {
+            hid: Mutex::new(hid),
+        }
+    }
+}
+
+impl DeviceSource for HidDeviceSource {
+    fn list(&self) -> Vec<(Kind, String)> {
+        let mut hid = self.hid.lock().unwrap();
+        // HidApi caches the device list, devices plugged in later only show up after a refresh.
+        if let Err(e) = refresh_device_list(&mut hid) {
+            print_error!("failed to refresh device list: {}", e);
+        }
+        list_devices(&hid)
+    }
+
+    fn connect(&self, kind: Kind, serial: &str) -> Result<SharedDeck, DeckError> {
+        let hid = self.hid.lock().unwrap();
+        let device = AsyncAjazz::connect(&hid, kind, serial)?;
+        Ok(Arc::new(AjazzDeck::new(device, serial.to_string())))
+    }
+}
+
+#[async_trait]
+impl DeckReader for AsyncDeviceStateReader {
+    async fn read(&self) -> Result<Vec<DeviceStateUpdate>, DeckError> {
+        let updates = AsyncDeviceStateReader::read(self, READER_POLL_RATE).await?;
+        Ok(updates)
+    }
+}
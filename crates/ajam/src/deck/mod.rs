@@ -0,0 +1,63 @@
+mod ajazz;
+mod virtual_deck;
+
+use std::sync::Arc;
+
+use ajazz_sdk::info::Kind;
+use ajazz_sdk::{AjazzError, DeviceStateUpdate};
+use async_trait::async_trait;
+use image::DynamicImage;
+use thiserror::Error;
+
+pub(crate) use ajazz::HidDeviceSource;
+#[cfg(test)]
+pub(crate) use virtual_deck::{DeckCall, VirtualDevices};
+pub(crate) use virtual_deck::VirtualDeck;
+
+#[derive(Error, Debug)]
+pub enum DeckError {
+    #[error("device error: {0}")]
+    Device(#[from] AjazzError),
+
+    #[error("device disconnected")]
+    Disconnected,
+}
+
+/// DeckBackend is a device the daemon renders to and receives input from.
+#[async_trait]
+pub(crate) trait DeckBackend: Send + Sync {
+    fn kind(&self) -> Kind;
+    fn serial(&self) -> String;
+    fn reader(&self) -> Arc<dyn DeckReader>;
+
+    async fn set_brightness(&self, brightness: u8) -> Result<(), DeckError>;
+    async fn set_button_image(&self, key: u8, image: DynamicImage) -> Result<(), DeckError>;
+    async fn clear_button_image(&self, key: u8) -> Result<(), DeckError>;
+    async fn clear_all_button_images(&self) -> Result<(), DeckError>;
+    async fn flush(&self) -> Result<(), DeckError>;
+}
+
+/// DeckReader is a source of input events from a deck.
+#[async_trait]
+pub(crate) trait DeckReader: Send + Sync {
+    async fn read(&self) -> Result<Vec<DeviceStateUpdate>, DeckError>;
+}
+
+pub(crate) type SharedDeck = Arc<dyn DeckBackend>;
+
+/// DeviceSource finds decks and connects to them.
+pub(crate) trait DeviceSource: Send + Sync {
+    /// Returns the kind and serial number of every device present.
+    fn list(&self) -> Vec<(Kind, String)>;
+    fn connect(&self, kind: Kind, serial: &str) -> Result<SharedDeck, DeckError>;
+}
+
+impl<T: DeviceSource> DeviceSource for Arc<T> {
+    fn list(&self) -> Vec<(Kind, String)> {
+        self.as_ref().list()
+    }
+
+    fn connect(&self, kind: Kind, serial: &str) -> Result<SharedDeck, DeckError> {
+        self.as_ref().connect(kind, serial)
+    }
+}
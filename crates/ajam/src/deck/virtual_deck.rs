@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use ajazz_sdk::info::Kind;
+use ajazz_sdk::DeviceStateUpdate;
+use async_trait::async_trait;
+use image::DynamicImage;
+use tokio::sync::mpsc;
+
+use super::{DeckBackend, DeckError, DeckReader};
+#[cfg(test)]
+use super::{DeviceSource, SharedDeck};
+
+/// DeckCall is a single call recorded by the virtual deck.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum DeckCall {
+    SetBrightness(u8),
+    SetButtonImage(u8),
+    ClearButtonImage(u8),
+    ClearAllButtonImages,
+    Flush,
+}
+
+/// VirtualDeck is an in-memory deck that records every call made to it.
+///
+/// Input events are injected with [`VirtualDeck::send`] and delivered through its reader.
+pub(crate) struct VirtualDeck {
+    kind: Kind,
+    serial: String,
+    connected: Arc<AtomicBool>,
+    calls: Mutex<Vec<DeckCall>>,
+    images: Mutex<HashMap<u8, DynamicImage>>,
+    events_tx: mpsc::UnboundedSender<Option<DeviceStateUpdate>>,
+    reader: Arc<VirtualDeckReader>,
+}
+
+struct VirtualDeckReader {
+    connected: Arc<AtomicBool>,
+    events_rx: tokio::sync::Mutex<mpsc::UnboundedReceiver<Option<DeviceStateUpdate>>>,
+}
+
+impl VirtualDeck {
+    pub fn new(kind: Kind) -> Self {
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        let connected = Arc::new(AtomicBool::new(true));
+        Self {
+            kind,
+            serial: "virtual".to_string(),
+            connected: connected.clone(),
+            calls: Mutex::new(Vec::new()),
+            images: Mutex::new(HashMap::new()),
+            events_tx,
+            reader: Arc::new(VirtualDeckReader {
+                connected,
+                events_rx: tokio::sync::Mutex::new(events_rx),
+            }),
+        }
+    }
+
+    /// Sets the serial number, e.g. to tell several virtual decks apart.
+    #[cfg(test)]
+    pub fn with_serial(mut self, serial: &str) -> Self {
+        self.serial = serial.to_string();
+        self
+    }
+
+    /// Injects an input event as if it came from the device.
+    #[allow(dead_code)]
+    pub fn send(&self, update: DeviceStateUpdate) {
+        let _ = self.events_tx.send(Some(update));
+    }
+
+    /// Simulates unplugging the device. Pending and future calls fail.
+    #[allow(dead_code)]
+    pub fn disconnect(&self) {
+        self.connected.store(false, Ordering::Relaxed);
+        let _ = self.events_tx.send(None);
+    }
+
+    #[cfg(test)]
+    pub fn calls(&self) -> Vec<DeckCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    #[cfg(test)]
+    pub fn take_calls(&self) -> Vec<DeckCall> {
+        std::mem::take(&mut *self.calls.lock().unwrap())
+    }
+
+    #[cfg(test)]
+    pub fn image(&self, key: u8) -> Option<DynamicImage> {
+        self.images.lock().unwrap().get(&key).cloned()
+    }
+
+    fn record(&self, call: DeckCall) -> Result<(), DeckError> {
+        if !self.connected.load(Ordering::Relaxed) {
+            return Err(DeckError::Disconnected);
+        }
+        self.calls.lock().unwrap().push(call);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DeckBackend for VirtualDeck {
+    fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    fn serial(&self) -> String {
+        self.serial.clone()
+    }
+
+    fn reader(&self) -> Arc<dyn DeckReader> {
+        self.reader.clone()
+    }
+
+    async fn set_brightness(&self, brightness: u8) -> Result<(), DeckError> {
+        self.record(DeckCall::SetBrightness(brightness))
+    }
+
+    async fn set_button_image(&self, key: u8, image: DynamicImage) -> Result<(), DeckError> {
+        self.record(DeckCall::SetButtonImage(key))?;
+        self.images.lock().unwrap().insert(key, image);
+        Ok(())
+    }
+
+    async fn clear_button_image(&self, key: u8) -> Result<(), DeckError> {
+        self.record(DeckCall::ClearButtonImage(key))?;
+        self.images.lock().unwrap().remove(&key);
+        Ok(())
+    }
+
+    async fn clear_all_button_images(&self) -> Result<(), DeckError> {
+        self.record(DeckCall::ClearAllButtonImages)?;
+        self.images.lock().unwrap().clear();
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<(), DeckError> {
+        self.record(DeckCall::Flush)
+    }
+}
+
+/// VirtualDevices is a device source of virtual decks that can be plugged in and out.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct VirtualDevices {
+    decks: Mutex<Vec<Arc<VirtualDeck>>>,
+}
+
+#[cfg(test)]
+impl VirtualDevices {
+    pub fn plug(&self, deck: Arc<VirtualDeck>) {
+        self.decks.lock().unwrap().push(deck);
+    }
+
+    /// Disconnects the deck with the serial number and removes it.
+    pub fn unplug(&self, serial: &str) {
+        self.decks.lock().unwrap().retain(|deck| {
+            if deck.serial == serial {
+                deck.disconnect();
+            }
+            deck.serial != serial
+        });
+    }
+}
+
+#[cfg(test)]
+impl DeviceSource for VirtualDevices {
+    fn list(&self) -> Vec<(Kind, String)> {
+        let decks = self.decks.lock().unwrap();
+        decks.iter().map(|deck| (deck.kind, deck.serial.clone())).collect()
+    }
+
+    fn connect(&self, _kind: Kind, serial: &str) -> Result<SharedDeck, DeckError> {
+        let decks = self.decks.lock().unwrap();
+        match decks.iter().find(|deck| deck.serial == serial) {
+            Some(deck) => Ok(deck.clone()),
+            None => Err(DeckError::Disconnected),
+        }
+    }
+}
+
+#[async_trait]
+impl DeckReader for VirtualDeckReader {
+    async fn read(&self) -> Result<Vec<DeviceStateUpdate>, DeckError> {
+        if !self.connected.load(Ordering::Relaxed) {
+            return Err(DeckError::Disconnected);
+        }
+
+        let mut events_rx = self.events_rx.lock().await;
+        let Some(Some(update)) = events_rx.recv().await else {
+            return Err(DeckError::Disconnected);
+        };
+
+        let mut updates = vec![update];
+        while let Ok(Some(update)) = events_rx.try_recv() {
+            updates.push(update);
+        }
+        Ok(updates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_records_calls() {
+        let deck = VirtualDeck::new(Kind::Akp03);
+        deck.set_brightness(50).await.unwrap();
+        deck.clear_button_image(1).await.unwrap();
+        deck.flush().await.unwrap();
+
+        assert_eq!(
+            deck.calls(),
+            vec![
+                DeckCall::SetBrightness(50),
+                DeckCall::ClearButtonImage(1),
+                DeckCall::Flush,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_injected_events() {
+        let deck = VirtualDeck::new(Kind::Akp03);
+        let reader = deck.reader();
+        deck.send(DeviceStateUpdate::ButtonDown(3));
+        deck.send(DeviceStateUpdate::EncoderTwist(1, -2));
+
+        let updates = reader.read().await.unwrap();
+        assert_eq!(updates.len(), 2);
+        assert!(matches!(updates[0], DeviceStateUpdate::ButtonDown(3)));
+        assert!(matches!(updates[1], DeviceStateUpdate::EncoderTwist(1, -2)));
+    }
+
+    #[tokio::test]
+    async fn test_disconnect() {
+        let deck = VirtualDeck::new(Kind::Akp03);
+        let reader = deck.reader();
+        deck.disconnect();
+
+        assert!(reader.read().await.is_err());
+        assert!(deck.flush().await.is_err());
+    }
+}
@@ -1,19 +1,26 @@
 mod logging;
 mod state;
 mod cli;
+mod config;
+mod control;
+mod deck;
 
 use ajam_launchctl::{LaunchAgent, LaunchControllable};
-use ajam_profile::open_profiles;
+use ajam_profile::{check_profiles, open_profiles, parse_kind};
+use ajazz_sdk::info::Kind;
+use ajazz_sdk::{list_devices, new_hidapi, AsyncAjazz};
 use clap::Parser;
+use deck::VirtualDeck;
 use fern::Dispatch;
-use state::{ActivityHandler, State, StateConnect};
-use std::{path::{Path, PathBuf}, process};
+use state::{ActivityHandler, Decks, State};
+use std::{path::{Path, PathBuf}, process, sync::Arc};
 use tokio::{task, signal};
 use colored::Colorize;
-use cli::{Cli, Command};
+use cli::{Cli, Command, ConfigCommand, CtlCommand};
+use config::{Config, DeviceFilter, CONFIG_FILE_NAME};
 use ajam_activity::Monitor;
 
-const APP_LABEL: &str = "co.myrt.ajam";
+const STATE_FILE_NAME: &str = "state.json";
 
 fn setup_logging(verbose: bool, no_color: bool) {
     let log_level = if verbose { log::LevelFilter::Debug } else { log::LevelFilter::Info };
@@ -28,8 +35,12 @@ fn setup_logging(verbose: bool, no_color: bool) {
     }
 }
 
-async fn run_listener(profiles_dir: &str) -> process::ExitCode {
-    let profiles_dir = Path::new(&profiles_dir);
+async fn run_listener(
+    profiles_dir: &Path,
+    virtual_deck: Option<Kind>,
+    config: Config,
+    state_file: PathBuf,
+) -> process::ExitCode {
     let profiles = match open_profiles(profiles_dir) {
         Ok(profiles) => profiles,
         Err(e) => {
@@ -42,26 +53,74 @@ async fn run_listener(profiles_dir: &str) -> process::ExitCode {
         print_warning!("App: {} - {} pages", app_id, profile.manifest.pages.len());
     }
 
-    let state = State::with_profiles(profiles);
+    let listener = match control::bind(&config.socket).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            print_error!("Failed to open control socket: {}", e);
+            return process::ExitCode::FAILURE;
+        }
+    };
+    let filter = DeviceFilter::new(&config.devices);
+    let state = State::with_profiles(profiles_dir.to_path_buf(), profiles)
+        .with_config(config)
+        .with_state_file(state_file);
+    let decks = Decks::new(state).with_device_filter(filter);
 
     let (monitor, rx) = Monitor::new();
 
-    let state_clone = state.clone();
+    let decks_clone = decks.clone();
     task::spawn(async move {
         print_debug!("Starting OS activity monitor listener");
-        state_clone.listen_activity_events(rx).await;
+        decks_clone.listen_activity_events(rx).await;
     });
 
-    let state_clone = state.clone();
+    let decks_clone = decks.clone();
     task::spawn(async move {
+        if let Some(kind) = virtual_deck {
+            print_info!("Using virtual {:?} deck", kind);
+            decks_clone.attach(Arc::new(VirtualDeck::new(kind))).await;
+            return;
+        }
         print_debug!("Starting device handler");
-        let mut state_device = state_clone;
-        state_device.connect_deck().await;
+        decks_clone.connect_decks().await;
+    });
+
+    let decks_clone = decks.clone();
+    task::spawn(async move {
+        print_debug!("Starting profiles watcher");
+        decks_clone.watch_profiles().await;
+    });
+
+    let decks_clone = decks.clone();
+    task::spawn(async move {
+        if let Err(e) = control::serve(decks_clone, listener).await {
+            print_error!("Control socket failed: {}", e);
+        }
+    });
+
+    let decks_clone = decks.clone();
+    task::spawn(async move {
+        decks_clone.watch_state_file().await;
     });
 
-    let state_clone = state.clone();
+    let decks_clone = decks.clone();
     task::spawn(async move {
-        handle_signals(state_clone).await;
+        decks_clone.watch_conditions().await;
+    });
+
+    let decks_clone = decks.clone();
+    task::spawn(async move {
+        decks_clone.watch_toggle_probes().await;
+    });
+
+    let decks_clone = decks.clone();
+    task::spawn(async move {
+        decks_clone.watch_image_commands().await;
+    });
+
+    let decks_clone = decks.clone();
+    task::spawn(async move {
+        handle_signals(decks_clone).await;
     });
 
     if let Err(e) = monitor.start_listening() {
@@ -71,7 +130,91 @@ async fn run_listener(profiles_dir: &str) -> process::ExitCode {
     process::ExitCode::SUCCESS
 }
 
-async fn handle_signals(state: State) {
+fn check(profiles_dir: &str) -> process::ExitCode {
+    let diagnostics = match check_profiles(profiles_dir) {
+        Ok(diagnostics) => diagnostics,
+        Err(e) => {
+            print_error!("Failed to read profiles: {}", e);
+            return process::ExitCode::FAILURE;
+        }
+    };
+
+    let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+    let warnings = diagnostics.len() - errors;
+    for diagnostic in diagnostics.iter() {
+        if diagnostic.is_error() {
+            print_error!("{}", diagnostic);
+        } else {
+            print_warning!("{}", diagnostic);
+        }
+    }
+
+    if errors > 0 {
+        print_error!("Found {} errors and {} warnings", errors, warnings);
+        return process::ExitCode::FAILURE;
+    }
+    print_info!("Profiles are valid ({} warnings)", warnings);
+    process::ExitCode::SUCCESS
+}
+
+async fn ctl(socket: &Path, deck: Option<&str>, command: CtlCommand) -> process::ExitCode {
+    let call = match command {
+        CtlCommand::Status => control::Call::Status,
+        CtlCommand::Navigate { page, profile } => control::Call::Navigate { profile, page },
+        CtlCommand::Brightness { value } => control::Call::SetBrightness { brightness: value },
+        CtlCommand::Press { key } => control::Call::Press { key },
+        CtlCommand::Reload => control::Call::Reload,
+        CtlCommand::Connection => control::Call::Connection,
+    };
+
+    match control::call(socket, deck, call).await {
+        Ok(result) => {
+            println!("{}", serde_json::to_string_pretty(&result).unwrap());
+            process::ExitCode::SUCCESS
+        }
+        Err(e) => {
+            print_error!("Request failed: {}", e);
+            process::ExitCode::FAILURE
+        }
+    }
+}
+
+fn load_config(path: &Path) -> Option<Config> {
+    match Config::load(path) {
+        Ok(config) => Some(config),
+        Err(e) => {
+            print_error!("{}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+async fn devices(filter: &DeviceFilter) -> process::ExitCode {
+    let hid_api = match new_hidapi() {
+        Ok(hid) => hid,
+        Err(e) => {
+            print_error!("Failed to create HidApi: {}", e);
+            return process::ExitCode::FAILURE;
+        }
+    };
+
+    let devices = list_devices(&hid_api);
+    if devices.is_empty() {
+        print_info!("No devices found");
+        return process::ExitCode::SUCCESS;
+    }
+    for (kind, serial) in devices {
+        let firmware = match AsyncAjazz::connect(&hid_api, kind, &serial) {
+            Ok(device) => device.firmware_version().await.unwrap_or_else(|e| e.to_string()),
+            Err(e) => e.to_string(),
+        };
+        let selected = if filter.matches(kind, &serial) { "" } else { " (skipped)" };
+        println!("{:?}\t{}\t{}{}", kind, serial, firmware, selected);
+    }
+    process::ExitCode::SUCCESS
+}
+
+async fn handle_signals(decks: Decks) {
     let mut term_signal = signal::unix::signal(signal::unix::SignalKind::terminate())
         .expect("Failed to create SIGTERM signal handler");
     
@@ -84,7 +227,8 @@ async fn handle_signals(state: State) {
         }
     }
     
-    match state.disconnect_deck().await {
+    decks.save_state().await;
+    match decks.disconnect_all().await {
         Ok(_) => {
             print_info!("Screen cleared");
         },
@@ -102,12 +246,36 @@ async fn main() -> process::ExitCode {
     let cli = Cli::parse();
     setup_logging(cli.verbose, cli.no_color);
 
-    let home = PathBuf::from(std::env::var("HOME").unwrap());
-    let default_profiles_dir = home.join("Library/Application Support/ajam/profiles");
+    let app_dir = config::app_dir();
+    let config_path = match cli.config.as_deref() {
+        Some(path) => PathBuf::from(path),
+        None => app_dir.join(CONFIG_FILE_NAME),
+    };
+    let Some(mut config) = load_config(&config_path) else {
+        return process::ExitCode::FAILURE;
+    };
+    let default_profiles_dir = config.profiles.get_or_insert_with(|| app_dir.join("profiles")).clone();
 
     match cli.command {
-        Command::Run { profiles } => {
-            run_listener(&profiles).await;
+        Command::Run { profiles, virtual_deck, socket, device } => {
+            let virtual_deck = match virtual_deck {
+                Some(device) => match parse_kind(&device) {
+                    Some(kind) => Some(kind),
+                    None => {
+                        print_error!("Unknown device: {}", device);
+                        return process::ExitCode::FAILURE;
+                    }
+                },
+                None => None,
+            };
+            let profiles = profiles.map(PathBuf::from).unwrap_or(default_profiles_dir);
+            if let Some(socket) = socket {
+                config.socket = PathBuf::from(socket);
+            }
+            if !device.is_empty() {
+                config.devices = device;
+            }
+            return run_listener(&profiles, virtual_deck, config, app_dir.join(STATE_FILE_NAME)).await;
         },
         Command::Start { profiles } => {
             let profiles = profiles.unwrap_or(default_profiles_dir.display().to_string());
@@ -117,15 +285,21 @@ async fn main() -> process::ExitCode {
             if cli.verbose {
                 arguments.push("--verbose".to_string());
             }
+            if cli.config.is_some() {
+                // The agent does not run in the current directory.
+                let config_path = std::path::absolute(&config_path).unwrap_or(config_path);
+                arguments.push("--config".to_string());
+                arguments.push(config_path.display().to_string());
+            }
             arguments.push("run".to_string());
             arguments.push("--profiles".to_string());
             arguments.push(profiles);
 
             let agent = LaunchAgent {
-                label: APP_LABEL.to_string(),
+                label: config.agent.label.clone(),
                 program_arguments: arguments,
-                standard_out_path: "/tmp/ajam.out".to_string(),
-                standard_error_path: "/tmp/ajam.err".to_string(),
+                standard_out_path: config.agent.stdout.display().to_string(),
+                standard_error_path: config.agent.stderr.display().to_string(),
                 keep_alive: true,
                 run_at_load: false,
             };
@@ -153,13 +327,24 @@ async fn main() -> process::ExitCode {
                 }
             }
         },
+        Command::Devices => {
+            return devices(&DeviceFilter::new(&config.devices)).await;
+        },
+        Command::Check { profiles } => {
+            let profiles = profiles.unwrap_or(default_profiles_dir.display().to_string());
+            return check(&profiles);
+        },
+        Command::Ctl { socket, deck, command } => {
+            let socket = socket.map(PathBuf::from).unwrap_or(config.socket);
+            return ctl(&socket, deck.as_deref(), command).await;
+        },
         Command::Stop => {
-            if !LaunchAgent::exists(APP_LABEL) {
+            if !LaunchAgent::exists(&config.agent.label) {
                 print_error!("Agent does not exist");
                 return process::ExitCode::FAILURE;
             }
 
-            let agent = LaunchAgent::from_file(APP_LABEL).unwrap();
+            let agent = LaunchAgent::from_file(&config.agent.label).unwrap();
 
             match agent.is_running().await {
                 Ok(true) => {
@@ -180,12 +365,12 @@ async fn main() -> process::ExitCode {
             }
         },
         Command::Status => {
-            if !LaunchAgent::exists(APP_LABEL) {
+            if !LaunchAgent::exists(&config.agent.label) {
                 print_info!("Agent does not exist");
                 return process::ExitCode::FAILURE;
             }
 
-            let agent = LaunchAgent::from_file(APP_LABEL).unwrap();
+            let agent = LaunchAgent::from_file(&config.agent.label).unwrap();
             match agent.is_running().await {
                 Ok(true) => {
                     print_info!("Agent is running");
@@ -199,6 +384,15 @@ async fn main() -> process::ExitCode {
                 }
             }
         },
+        Command::Config { command: ConfigCommand::Show } => {
+            match serde_yaml::to_string(&config) {
+                Ok(yaml) => print!("{}", yaml),
+                Err(e) => {
+                    print_error!("Failed to print config: {}", e);
+                    return process::ExitCode::FAILURE;
+                }
+            }
+        },
     }
 
     process::ExitCode::SUCCESS
@@ -2,60 +2,152 @@ use std::sync::mpsc;
 use colored::Colorize;
 
 use ajam_activity::Event;
+use ajam_profile::ConditionContext;
 
 use crate::{print_debug, print_error};
 
 
 use super::{
-    navigation::{Navigator, DEFAULT_PROFILE}, render::StateRender, State
+    is_for_deck, navigation::Navigator, State
 };
 
 pub(crate) trait ActivityHandler {
     async fn listen_activity_events(&self, rx: mpsc::Receiver<Event>);
 }
 
-impl ActivityHandler for State {
-    async fn listen_activity_events(&self, rx: mpsc::Receiver<Event>) {
-        while let Ok(event) = rx.recv() {
-            match event {
-                Event::AppChange(bundle_id) => {
-                    let profile = {
-                        let navigation_guard = self.navigation.read().await;
-                        navigation_guard.profile.clone()
-                    };
-
-                    if profile == bundle_id {
-                        continue;
-                    }
-
-                    {
-                        let mut active_profile_guard = self.active_profile.write().await;
-                        *active_profile_guard = DEFAULT_PROFILE.to_string();
-                    }
+impl State {
+    /// Shows the profile matching the focused app, its window and the conditions,
+    /// falling back to the default one, then the page whose condition started holding.
+    pub(super) async fn show_app_profile(
+        &self,
+        bundle_id: &str,
+        title: Option<&str>,
+        context: &ConditionContext,
+    ) {
+        if !bundle_id.is_empty() {
+            let deck = self.deck_id().await;
+            let profile = {
+                let profiles = self.profiles.read().await;
+                let is_for_this_deck = |name: &str| {
+                    profiles.get(name).is_some_and(|p| is_for_deck(p, deck.as_ref()))
+                };
+                let matcher = self.matcher.read().await;
+                matcher
+                    .find_where(bundle_id, title, context, is_for_this_deck)
+                    .map(str::to_string)
+            };
+            let profile = match profile {
+                Some(profile) => profile,
+                None => self.default_profile().await,
+            };
 
-                    if let Err(e) = self.navigate_to_profile_or_default(&bundle_id).await {
+            // Events keeping the match leave the deck alone, its profile may have been changed.
+            let mut matched = self.matched_profile.lock().await;
+            if matched.as_deref() != Some(profile.as_str()) {
+                print_debug!("Focused {} ({:?}), profile {}", bundle_id, title, profile);
+                match self.navigate_to_profile_or_default(&profile).await {
+                    Ok(()) => *matched = Some(profile),
+                    Err(e) => {
+                        *matched = None;
                         print_error!("error navigating to profile: {:?}", e);
                     }
                 }
-                Event::AudioOutputChange(device_name) => {
-                    self.set_audio_output_device(&device_name).await;
+            }
+        }
 
-                    print_debug!("Audio output changed: {}", device_name);
+        if let Err(e) = self.show_conditional_pages(context).await {
+            print_error!("error showing conditional page: {:?}", e);
+        }
+    }
+}
 
-                    if let Err(e) = self.render_active_page().await {
-                        print_error!("error rendering active page: {:?}", e);
-                    }
-                }
-                Event::AudioInputChange(device_name) => {
-                    self.set_audio_input_device(&device_name).await;
+#[cfg(test)]
+mod tests {
+    use ajam_profile::open_profiles;
 
-                    print_debug!("Audio input changed: {}", device_name);
+    use crate::state::testing::{attached_state, attached_state_with, write_profile, TEST_MANIFEST};
+    use crate::state::Decks;
 
-                    if let Err(e) = self.render_active_page().await {
-                        print_error!("error rendering active page: {:?}", e);
-                    }
-                }
-            }
-        }
+    use super::*;
+
+    #[tokio::test]
+    async fn test_window_title_selects_profile() {
+        let (state, _deck, dir) = attached_state().await;
+        write_profile(dir.path(), "com.jetbrains.intellij", TEST_MANIFEST, &["a.bmp", "b.bmp"]);
+        let manifest = format!(
+            "match:\n  apps: [\"com.jetbrains.*\"]\n  title: \"^ajam\"\n{}",
+            TEST_MANIFEST
+        );
+        write_profile(dir.path(), "ajam", &manifest, &["a.bmp", "b.bmp"]);
+        state.set_profiles(open_profiles(dir.path()).unwrap()).await;
+        let decks = Decks::with_state(state.clone()).await;
+        let intellij_title = |title: &str| {
+            Event::WindowTitleChange("com.jetbrains.intellij".to_string(), title.to_string())
+        };
+
+        let (tx, rx) = mpsc::channel();
+        tx.send(Event::AppChange("com.jetbrains.intellij".to_string())).unwrap();
+        tx.send(intellij_title("ajam – main.rs")).unwrap();
+        tx.send(Event::AppChange("com.jetbrains.goland".to_string())).unwrap();
+        drop(tx);
+        decks.listen_activity_events(rx).await;
+        assert_eq!(state.navigation.read().await.profile, "common");
+
+        // The title of a window may arrive before the change of its app.
+        let (tx, rx) = mpsc::channel();
+        tx.send(intellij_title("ajam – main.rs")).unwrap();
+        tx.send(Event::AppChange("com.jetbrains.intellij".to_string())).unwrap();
+        drop(tx);
+        decks.listen_activity_events(rx).await;
+        assert_eq!(state.navigation.read().await.profile, "ajam");
+
+        let (tx, rx) = mpsc::channel();
+        tx.send(intellij_title("website – index.html")).unwrap();
+        drop(tx);
+        decks.listen_activity_events(rx).await;
+        assert_eq!(state.navigation.read().await.profile, "com.jetbrains.intellij");
+
+        // Titles keeping the match leave a profile opened by hand.
+        state.navigate_to("common", "main").await.unwrap();
+        let (tx, rx) = mpsc::channel();
+        tx.send(intellij_title("website – style.css")).unwrap();
+        drop(tx);
+        decks.listen_activity_events(rx).await;
+        assert_eq!(state.navigation.read().await.profile, "common");
+    }
+
+    #[tokio::test]
+    async fn test_conditional_page() {
+        let manifest = TEST_MANIFEST.replace(
+            "  second:\n",
+            "  second:\n    when:\n      audio_output: AirPods*\n",
+        );
+        let (state, _deck, _dir) = attached_state_with(&manifest).await;
+        let decks = Decks::with_state(state.clone()).await;
+        let page = || async { state.navigation.read().await.page.clone() };
+
+        let (tx, rx) = mpsc::channel();
+        tx.send(Event::AudioOutputChange("AirPods Pro".to_string())).unwrap();
+        drop(tx);
+        decks.listen_activity_events(rx).await;
+        assert_eq!(page().await, "second");
+
+        // The page is not forced back after leaving it while the condition holds.
+        state.navigate_to_page("main").await.unwrap();
+        let (tx, rx) = mpsc::channel();
+        tx.send(Event::AudioInputChange("AirPods Pro".to_string())).unwrap();
+        tx.send(Event::AudioOutputChange("Speakers".to_string())).unwrap();
+        drop(tx);
+        decks.listen_activity_events(rx).await;
+        assert_eq!(page().await, "main");
+
+        let (tx, rx) = mpsc::channel();
+        tx.send(Event::AudioOutputChange("AirPods Max".to_string())).unwrap();
+        tx.send(Event::AudioOutputChange("Speakers".to_string())).unwrap();
+        drop(tx);
+        decks.listen_activity_events(rx).await;
+        // Leaving returns to the page the conditional one was opened from.
+        assert_eq!(page().await, "main");
+        assert_eq!(state.navigation.read().await.stack.len(), 2);
     }
 }
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ajam_profile::{Animation, KeyImage};
+use colored::Colorize;
+use image::DynamicImage;
+use tokio::time::{sleep_until, Instant};
+
+use crate::print_debug;
+use crate::deck::DeckError;
+
+use super::State;
+
+#[derive(Debug)]
+struct Running {
+    animation: Arc<Animation>,
+    started: Instant,
+    frame: usize,
+    deadline: Instant,
+}
+
+/// Animator tracks the animated keys of the shown page.
+#[derive(Debug, Default)]
+pub(crate) struct Animator {
+    keys: HashMap<u8, Running>,
+}
+
+impl Animator {
+    /// Starts the animations of a page and returns the images to show now.
+    ///
+    /// Keys showing the same animation as before keep playing from their current frame.
+    pub fn show(&mut self, images: &[Option<KeyImage>], now: Instant) -> Vec<Option<DynamicImage>> {
+        let mut keys = HashMap::new();
+        let shown = images
+            .iter()
+            .enumerate()
+            .map(|(index, image)| {
+                let key = index as u8;
+                match image.as_ref()? {
+                    KeyImage::Static(image) => Some(image.clone()),
+                    KeyImage::Animated(animation) => {
+                        let started = match self.keys.remove(&key) {
+                            Some(running) if Arc::ptr_eq(&running.animation, animation) => {
+                                running.started
+                            }
+                            _ => now,
+                        };
+                        let (frame, remaining) = animation.frame_at(now - started);
+                        keys.insert(
+                            key,
+                            Running {
+                                animation: animation.clone(),
+                                started,
+                                frame,
+                                deadline: now + remaining,
+                            },
+                        );
+                        Some(animation.frames()[frame].image.clone())
+                    }
+                }
+            })
+            .collect();
+        self.keys = keys;
+        shown
+    }
+
+    /// Stops every animation, e.g. when the deck is gone.
+    pub fn stop(&mut self) {
+        self.keys.clear();
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.keys.values().map(|running| running.deadline).min()
+    }
+
+    /// Returns the keys whose frame changed by `now` along with their new frame.
+    pub fn advance(&mut self, now: Instant) -> Vec<(u8, DynamicImage)> {
+        let mut frames = Vec::new();
+        for (key, running) in self.keys.iter_mut() {
+            if running.deadline > now {
+                continue;
+            }
+            let (frame, remaining) = running.animation.frame_at(now - running.started);
+            running.deadline = now + remaining;
+            if frame != running.frame {
+                running.frame = frame;
+                frames.push((*key, running.animation.frames()[frame].image.clone()));
+            }
+        }
+        frames.sort_by_key(|(key, _)| *key);
+        frames
+    }
+}
+
+pub(crate) trait StateAnimate {
+    /// Plays the animations of the shown page until the process exits.
+    async fn watch_animations(&self);
+}
+
+impl State {
+    /// Pushes the due animation frames to the deck, flushing once.
+    async fn render_animation_frames(&self) -> Result<(), DeckError> {
+        let dev = self.dev.read().await.clone();
+        let mut page_cache = self.page_cache.lock().await;
+        let mut animator = self.animator.lock().await;
+        let Some(dev) = dev else {
+            animator.stop();
+            return Ok(());
+        };
+
+        let frames = animator.advance(Instant::now());
+        if frames.is_empty() {
+            return Ok(());
+        }
+        for (key, image) in frames {
+            dev.set_button_image(key, image.clone()).await?;
+            if let Some(cached) = page_cache.get_mut(key as usize) {
+                *cached = Some(image);
+            }
+        }
+        dev.flush().await
+    }
+}
+
+impl StateAnimate for State {
+    async fn watch_animations(&self) {
+        loop {
+            let deadline = self.animator.lock().await.next_deadline();
+            let Some(deadline) = deadline else {
+                self.animation_changed.notified().await;
+                continue;
+            };
+
+            tokio::select! {
+                _ = sleep_until(deadline) => {}
+                _ = self.animation_changed.notified() => continue,
+            }
+
+            if let Err(e) = self.render_animation_frames().await {
+                print_debug!("Failed to render animation frame: {}", e);
+                self.animator.lock().await.stop();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ajam_profile::Frame;
+    use image::codecs::gif::GifEncoder;
+    use image::{Delay, Rgba, RgbaImage};
+
+    use crate::deck::DeckCall;
+    use crate::state::render::StateRender;
+    use crate::state::testing::{attached_state_with, eventually, TEST_MANIFEST};
+
+    use super::*;
+
+    fn animation() -> KeyImage {
+        let frames = (0..3)
+            .map(|color| Frame {
+                image: DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([color, 0, 0, 255]))),
+                delay: Duration::from_millis(100),
+            })
+            .collect();
+        KeyImage::Animated(Arc::new(Animation::new(frames)))
+    }
+
+    fn color(image: &DynamicImage) -> u8 {
+        image.to_rgba8().get_pixel(0, 0)[0]
+    }
+
+    #[test]
+    fn test_advance_only_changed_keys() {
+        let mut animator = Animator::default();
+        let now = Instant::now();
+        let still = KeyImage::Static(DynamicImage::ImageRgba8(RgbaImage::new(1, 1)));
+
+        let shown = animator.show(&[Some(still), None, Some(animation())], now);
+        assert_eq!(shown.len(), 3);
+        assert!(shown[1].is_none());
+        assert_eq!(color(shown[2].as_ref().unwrap()), 0);
+        assert_eq!(animator.next_deadline(), Some(now + Duration::from_millis(100)));
+
+        assert!(animator.advance(now + Duration::from_millis(50)).is_empty());
+        let frames = animator.advance(now + Duration::from_millis(100));
+        assert_eq!(frames.len(), 1);
+        assert_eq!((frames[0].0, color(&frames[0].1)), (2, 1));
+    }
+
+    #[test]
+    fn test_show_keeps_running_animation() {
+        let mut animator = Animator::default();
+        let now = Instant::now();
+        let image = animation();
+
+        animator.show(&[Some(image.clone())], now);
+        let shown = animator.show(&[Some(image)], now + Duration::from_millis(150));
+        assert_eq!(color(shown[0].as_ref().unwrap()), 1);
+
+        animator.show(&[None], now + Duration::from_millis(200));
+        assert_eq!(animator.next_deadline(), None);
+    }
+
+    #[tokio::test]
+    async fn test_animation_plays_on_deck() {
+        let manifest = TEST_MANIFEST.replace("src: a.bmp", "src: spinner.gif");
+        let (state, deck, dir) = attached_state_with(&manifest).await;
+
+        let file = std::fs::File::create(dir.path().join("common/spinner.gif")).unwrap();
+        let mut encoder = GifEncoder::new(file);
+        for color in [0, 255] {
+            let image = RgbaImage::from_pixel(8, 8, Rgba([color, 0, 0, 255]));
+            let delay = Delay::from_numer_denom_ms(50, 1);
+            encoder.encode_frame(image::Frame::from_parts(image, 0, 0, delay)).unwrap();
+        }
+        drop(encoder);
+
+        state.render_active_page().await.unwrap();
+        let state_clone = state.clone();
+        tokio::spawn(async move { state_clone.watch_animations().await });
+        deck.take_calls();
+
+        eventually(|| async {
+            deck.calls() == vec![DeckCall::SetButtonImage(0), DeckCall::Flush]
+        })
+        .await;
+    }
+}
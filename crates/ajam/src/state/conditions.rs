@@ -0,0 +1,217 @@
+use std::collections::{HashMap, HashSet};
+use std::future;
+use std::time::Duration;
+
+use ajam_profile::{Condition, ConditionContext, Manifest, TimeRange};
+use chrono::{Local, Timelike};
+use tokio::task::JoinSet;
+use tokio::time::{interval, sleep, MissedTickBehavior};
+
+use super::navigation::{Location, NavigationError, Navigator, Transition};
+use super::toggle::run_probe;
+use super::{Decks, State};
+
+/// ConditionProbeInterval is how often the commands of conditions are run,
+/// it is the default interval of toggle probes.
+const CONDITION_PROBE_INTERVAL: Duration = Duration::from_secs(5);
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// Returns the conditions of the manifest and its pages.
+fn manifest_conditions(manifest: &Manifest) -> impl Iterator<Item = &Condition> {
+    let pages = manifest.pages.values().filter_map(|page| page.when.as_ref());
+    manifest.when.iter().chain(pages)
+}
+
+/// Returns the minutes from `now` to the next start or end of the ranges, at most a day.
+fn minutes_to_time_boundary(ranges: &[TimeRange], now: u32) -> Option<u32> {
+    ranges
+        .iter()
+        .flat_map(|range| [range.start, range.end])
+        .map(|boundary| {
+            // Boundaries at `now` are a day away.
+            (boundary + MINUTES_PER_DAY - now % MINUTES_PER_DAY - 1) % MINUTES_PER_DAY + 1
+        })
+        .min()
+}
+
+impl Decks {
+    /// Re-evaluates the conditions of the profiles when the results of their commands
+    /// change and when one of their time ranges starts or ends.
+    ///
+    /// Commands are run here periodically, so handling events never waits for them.
+    pub async fn watch_conditions(&self) {
+        let mut ticker = interval(CONDITION_PROBE_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            let boundary = self.shared().time_to_time_boundary().await;
+            let boundary_reached = async {
+                match boundary {
+                    Some(boundary) => sleep(boundary).await,
+                    None => future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = ticker.tick() => {
+                    if !self.shared().probe_conditions().await {
+                        continue;
+                    }
+                }
+                _ = boundary_reached => {}
+            }
+            self.show_focused_profiles().await;
+        }
+    }
+}
+
+impl State {
+    /// Collects the system state, commands results are the ones of the last probe.
+    pub(super) async fn condition_context(&self) -> ConditionContext {
+        let now = Local::now();
+        ConditionContext {
+            audio_output: self.audio_output_device.read().await.clone(),
+            audio_input: self.audio_input_device.read().await.clone(),
+            displays: self.displays.read().await.clone(),
+            time: now.hour() * 60 + now.minute(),
+            commands: self.condition_commands.read().await.clone(),
+        }
+    }
+
+    /// Runs every command the conditions of the profiles depend on concurrently,
+    /// returns true if any result changed.
+    pub(super) async fn probe_conditions(&self) -> bool {
+        let commands: HashSet<String> = {
+            let profiles = self.profiles.read().await;
+            profiles
+                .values()
+                .flat_map(|profile| manifest_conditions(&profile.manifest))
+                .flat_map(Condition::commands)
+                .map(str::to_string)
+                .collect()
+        };
+
+        let mut probes = JoinSet::new();
+        for command in commands {
+            probes.spawn(async move {
+                let success = run_probe(&command).await;
+                (command, success)
+            });
+        }
+        let mut results = HashMap::new();
+        while let Some(probed) = probes.join_next().await {
+            if let Ok((command, Some(success))) = probed {
+                results.insert(command, success);
+            }
+        }
+
+        let mut commands = self.condition_commands.write().await;
+        let changed = *commands != results;
+        *commands = results;
+        changed
+    }
+
+    /// Returns the time until a time range of the conditions of the profiles starts or ends.
+    async fn time_to_time_boundary(&self) -> Option<Duration> {
+        let ranges: Vec<TimeRange> = {
+            let profiles = self.profiles.read().await;
+            profiles
+                .values()
+                .flat_map(|profile| manifest_conditions(&profile.manifest))
+                .flat_map(Condition::time_ranges)
+                .collect()
+        };
+        let now = Local::now();
+        let minutes = minutes_to_time_boundary(&ranges, now.hour() * 60 + now.minute())?;
+        Some(Duration::from_secs(u64::from(minutes * 60 - now.second())))
+    }
+
+    /// Shows a page of the current profile once its condition starts holding,
+    /// and leaves the shown page once its condition stops holding.
+    pub(super) async fn show_conditional_pages(
+        &self,
+        context: &ConditionContext,
+    ) -> Result<(), NavigationError> {
+        let (location, stack_depth) = {
+            let navigation = self.navigation.read().await;
+            (navigation.location(), navigation.stack.len())
+        };
+        let Ok(profile) = self.get_profile(&location.profile).await else {
+            return Ok(());
+        };
+
+        let holding: Vec<Location> = profile
+            .manifest
+            .pages_order
+            .iter()
+            .filter(|name| {
+                let when = profile.manifest.get_page(name).and_then(|page| page.when.as_ref());
+                when.is_some_and(|when| when.evaluate(context))
+            })
+            .map(|name| Location::new(&profile.name, name))
+            .collect();
+
+        let previous = {
+            let mut conditional_pages = self.conditional_pages.lock().await;
+            std::mem::replace(&mut *conditional_pages, holding.iter().cloned().collect())
+        };
+
+        if let Some(started) = holding.iter().find(|page| !previous.contains(*page)) {
+            if *started != location {
+                return self.navigate_to(&started.profile, &started.page).await;
+            }
+            return Ok(());
+        }
+
+        let stopped = previous.contains(&location) && !holding.contains(&location);
+        if !stopped {
+            return Ok(());
+        }
+        if stack_depth > 0 {
+            return self.navigate_back().await;
+        }
+        let main = Location::new(&location.profile, self.default_page());
+        self.show_location(main, Transition::Replace).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::testing::{attached_state_with, TempDir, TEST_MANIFEST};
+
+    use super::*;
+
+    #[test]
+    fn test_minutes_to_time_boundary() {
+        let range = |range: &str| TimeRange::try_from(range.to_string()).unwrap();
+        let ranges = [range("09:00-18:00"), range("22:30-06:00")];
+
+        assert_eq!(minutes_to_time_boundary(&ranges, 8 * 60), Some(60));
+        assert_eq!(minutes_to_time_boundary(&ranges, 9 * 60), Some(9 * 60));
+        assert_eq!(minutes_to_time_boundary(&ranges, 23 * 60), Some(7 * 60));
+        assert_eq!(minutes_to_time_boundary(&[range("12:00-24:00")], 23 * 60 + 59), Some(1));
+        assert_eq!(minutes_to_time_boundary(&[], 0), None);
+    }
+
+    #[tokio::test]
+    async fn test_probe_conditions() {
+        let flag_dir = TempDir::new();
+        let flag = flag_dir.path().join("flag");
+        let command = format!("test -e {}", flag.display());
+        let manifest = TEST_MANIFEST.replace(
+            "  second:\n",
+            &format!("  second:\n    when:\n      command: \"{}\"\n", command),
+        );
+        let (state, _deck, _dir) = attached_state_with(&manifest).await;
+
+        assert!(state.probe_conditions().await);
+        assert_eq!(state.condition_context().await.commands.get(&command), Some(&false));
+        assert!(!state.probe_conditions().await);
+
+        // Contexts use the last results instead of running the commands.
+        std::fs::write(&flag, "").unwrap();
+        assert_eq!(state.condition_context().await.commands.get(&command), Some(&false));
+        assert!(state.probe_conditions().await);
+        assert_eq!(state.condition_context().await.commands.get(&command), Some(&true));
+    }
+}
@@ -1,34 +1,115 @@
-use crate::{print_debug, print_error, print_warning};
-use crate::state::State;
-use ajazz_sdk::{list_devices, new_hidapi, AjazzError};
+use crate::deck::{DeckError, DeviceSource, HidDeviceSource, SharedDeck};
+use crate::{print_debug, print_error, print_info};
+use crate::config::ConnectionConfig;
+use crate::state::{Decks, State};
+use ajazz_sdk::new_hidapi;
+use std::collections::HashSet;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::task;
 use tokio::time::sleep;
 use colored::Colorize;
 
 use crate::state::render::StateRender;
 use crate::state::events::StateEventsHandler;
-
-const MAX_CONNECTION_ATTEMPTS: u8 = 10;
-const CONNECTION_RETRY_INTERVAL: u64 = 1;
-const CONNECTION_FAILURE_RETRY_INTERVAL: u64 = 10;
-const DEVICE_KEEPALIVE_CHECK_INTERVAL: u64 = 5;
+use crate::state::navigation::{Location, NavigationError, Transition};
 
 pub trait StateConnect {
-    async fn connect_deck(&mut self);
-    async fn disconnect_deck(&self) -> Result<(), AjazzError>;
+    async fn attach_deck(&self, deck: SharedDeck);
+    /// Forgets the deck without talking to it, e.g. when it is gone.
+    async fn detach_deck(&self);
+    async fn disconnect_deck(&self) -> Result<(), DeckError>;
 }
 
 impl StateConnect for State {
-    async fn disconnect_deck(&self) -> Result<(), AjazzError> {
-        let mut dev_guard = self.dev.write().await;
-        if let Some(dev) = dev_guard.as_ref() {
+    async fn attach_deck(&self, deck: SharedDeck) {
+        self.detach_deck().await;
+        *self.dev.write().await = Some(deck.clone());
+        self.page_cache.lock().await.invalidate();
+
+        let reader = deck.reader();
+        let state_clone = self.clone();
+        let task = tokio::spawn(async move {
+            state_clone.listen_device_events(reader).await;
+            state_clone.connection_lost.notify_one();
+        });
+        *self.device_task.lock().await = Some(task);
+
+        if let Err(e) = self.apply_brightness().await {
+            print_error!("failed to apply brightness: {}", e);
+        }
+        // The page shown before may belong to a profile made for another deck.
+        let result = match self.get_active_page().await {
+            Some(_) => self.render_active_page().await.map_err(NavigationError::from),
+            None => {
+                let location = Location::new(&self.default_profile().await, self.default_page());
+                self.show_location(location, Transition::Reset).await
+            }
+        };
+        if let Err(e) = result {
+            print_error!("failed to render active page: {}", e);
+        }
+    }
+
+    async fn detach_deck(&self) {
+        if let Some(task) = self.device_task.lock().await.take() {
+            task.abort();
+        }
+        *self.dev.write().await = None;
+        self.animator.lock().await.stop();
+    }
+
+    async fn disconnect_deck(&self) -> Result<(), DeckError> {
+        let dev = self.dev.read().await.clone();
+        if let Some(dev) = dev {
             dev.clear_all_button_images().await?;
-            *dev_guard = None;
         }
+        self.detach_deck().await;
         Ok(())
     }
+}
+
+impl State {
+    /// Returns whether the input of the deck is still read, reading fails once the deck is gone.
+    async fn is_deck_listening(&self) -> bool {
+        match self.device_task.lock().await.as_ref() {
+            Some(task) => !task.is_finished(),
+            None => false,
+        }
+    }
+}
+
+/// Backoff is the delay before the next device search after a device failed to connect,
+/// it doubles with every search a device fails in.
+struct Backoff {
+    delay: Duration,
+    min: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    fn new(config: &ConnectionConfig) -> Self {
+        Self {
+            delay: config.min_scan_interval(),
+            min: config.min_scan_interval(),
+            max: config.max_scan_interval(),
+        }
+    }
 
-    async fn connect_deck(&mut self) {
+    fn reset(&mut self) {
+        self.delay = self.min;
+    }
+
+    fn next(&mut self) -> Duration {
+        let delay = self.delay;
+        self.delay = (self.delay * 2).min(self.max);
+        delay
+    }
+}
+
+impl Decks {
+    /// Connects every device found over HID and keeps them connected.
+    pub async fn connect_decks(&self) {
         let hid_api = match new_hidapi() {
             Ok(hid) => hid,
             Err(e) => {
@@ -36,66 +117,162 @@ impl StateConnect for State {
                 return;
             }
         };
+        self.supervise(HidDeviceSource::new(hid_api)).await;
+    }
 
+    /// Connects the devices of the source, reconnecting the lost ones.
+    ///
+    /// Devices are searched for when a HID device is connected and when a deck stops
+    /// reading input, with a fallback search at the maximum scan interval. While a device
+    /// fails to connect, searches back off exponentially from the minimum interval.
+    pub async fn supervise(&self, source: impl DeviceSource + 'static) {
+        let source = Arc::new(source);
+        let connection = &self.shared().config.connection;
+        let mut backoff = Backoff::new(connection);
+        let mut skipped = HashSet::new();
         loop {
-            let is_connected = {
-                let dev_guard = self.dev.read().await;
-                match &*dev_guard {
-                    Some(device) => device.keep_alive().await.is_ok(),
-                    None => false,
+            for state in self.states().await {
+                if state.is_deck_listening().await {
+                    continue;
+                }
+                if let Some((_, serial)) = state.deck_id().await {
+                    self.detach(&serial).await;
                 }
-            };
-
-            if is_connected {
-                sleep(Duration::from_secs(DEVICE_KEEPALIVE_CHECK_INTERVAL)).await;
-                continue;
-            } else {
-                *self.dev.write().await = None;
             }
 
             print_debug!("Searching for AJazz devices...");
-            let mut attempt_count = 0;
-            let mut connected = false;
-
-            while attempt_count < MAX_CONNECTION_ATTEMPTS && !connected {
-                for (kind, serial) in list_devices(&hid_api) {
-                    print_debug!("found device: {:?} {}", kind, serial);
-
-                    match ajazz_sdk::AsyncAjazz::connect(&hid_api, kind, &serial) {
-                        Ok(device) => {
-                            *self.dev.write().await = Some(device.clone());
-
-                            let reader = device.get_reader();
-                            let state_clone = self.clone();
-                            tokio::spawn(async move {
-                                state_clone.listen_device_events(reader).await;
-                            });
-
-                            connected = true;
-                            if let Err(e) = self.apply_brightness().await {
-                                print_error!("failed to apply brightness: {}", e);
-                            }
-                            if let Err(e) = self.render_active_page().await {
-                                print_error!("failed to render active page: {}", e);
-                            }
-                            break;
-                        }
-                        Err(e) => {
-                            print_error!("failed to connect: {}", e);
-                        }
+            // HID I/O blocks, it is kept off the runtime threads.
+            let listed = {
+                let source = source.clone();
+                task::spawn_blocking(move || source.list()).await
+            };
+            let devices = listed.unwrap_or_else(|e| {
+                print_error!("failed to list devices: {}", e);
+                Vec::new()
+            });
+
+            let mut failed = false;
+            for (kind, serial) in devices {
+                if self.get(&serial).await.is_some() {
+                    continue;
+                }
+                if !self.device_filter().matches(kind, &serial) {
+                    if skipped.insert(serial.clone()) {
+                        print_info!("Skipping {:?} deck {}, it is not selected in the config", kind, serial);
                     }
+                    continue;
                 }
+                print_debug!("found device: {:?} {}", kind, serial);
 
-                if !connected {
-                    attempt_count += 1;
-                    sleep(Duration::from_secs(CONNECTION_RETRY_INTERVAL)).await;
+                let connected = {
+                    let source = source.clone();
+                    task::spawn_blocking(move || source.connect(kind, &serial)).await
+                };
+                let connected = connected
+                    .map_err(|e| e.to_string())
+                    .and_then(|connected| connected.map_err(|e| e.to_string()));
+                match connected {
+                    Ok(deck) => self.attach(deck).await,
+                    Err(e) => {
+                        print_error!("failed to connect: {}", e);
+                        failed = true;
+                    }
                 }
             }
 
-            if !connected {
-                print_warning!("failed to connect after {MAX_CONNECTION_ATTEMPTS} attempts. retrying in {CONNECTION_FAILURE_RETRY_INTERVAL} seconds...");
-                sleep(Duration::from_secs(CONNECTION_FAILURE_RETRY_INTERVAL)).await;
+            let delay = if failed {
+                backoff.next()
+            } else {
+                backoff.reset();
+                connection.max_scan_interval()
+            };
+            tokio::select! {
+                _ = self.shared().connection_lost.notified() => {
+                    print_debug!("Deck connection lost");
+                }
+                _ = self.device_added().notified() => {
+                    print_debug!("HID device connected");
+                }
+                _ = sleep(delay) => {}
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use ajam_activity::Event;
+    use ajam_profile::open_profiles;
+    use ajazz_sdk::info::Kind;
+
+    use crate::deck::{VirtualDeck, VirtualDevices};
+    use crate::state::ActivityHandler;
+    use crate::state::navigation::Navigator;
+    use crate::state::testing::{eventually, write_profile, TempDir, TEST_MANIFEST};
+
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let config = ConnectionConfig::default();
+        let mut backoff = Backoff::new(&config);
+        assert_eq!(backoff.next(), config.min_scan_interval());
+        assert_eq!(backoff.next(), config.min_scan_interval() * 2);
+        for _ in 0..10 {
+            backoff.next();
+        }
+        assert_eq!(backoff.next(), config.max_scan_interval());
+        backoff.reset();
+        assert_eq!(backoff.next(), config.min_scan_interval());
+    }
+
+    #[tokio::test]
+    async fn test_reconnect() {
+        tokio::time::pause();
+        let dir = TempDir::new();
+        write_profile(dir.path(), "common", TEST_MANIFEST, &["a.bmp", "b.bmp"]);
+        let state = State::with_profiles(dir.path().to_path_buf(), open_profiles(dir.path()).unwrap());
+        let decks = Decks::new(state);
+
+        let devices = Arc::new(VirtualDevices::default());
+        devices.plug(Arc::new(VirtualDeck::new(Kind::Akp03)));
+        let supervisor = {
+            let decks = decks.clone();
+            let devices = devices.clone();
+            task::spawn(async move { decks.supervise(devices).await })
+        };
+
+        let attached = || async { decks.get("virtual").await.is_some() };
+        eventually(attached).await;
+        let state = decks.get("virtual").await.unwrap();
+        state.navigate_to_page("second").await.unwrap();
+
+        // A connected HID device wakes the supervisor up.
+        tokio::time::advance(Duration::from_secs(60)).await;
+        devices.plug(Arc::new(VirtualDeck::new(Kind::Akp03).with_serial("SECOND")));
+        let (tx, rx) = mpsc::channel();
+        tx.send(Event::HidDeviceAdded).unwrap();
+        drop(tx);
+        decks.listen_activity_events(rx).await;
+        eventually(|| async { decks.get("SECOND").await.is_some() }).await;
+
+        // Devices missed by notifications are found by the fallback search.
+        devices.plug(Arc::new(VirtualDeck::new(Kind::Akp03).with_serial("THIRD")));
+        tokio::time::advance(ConnectionConfig::default().max_scan_interval()).await;
+        eventually(|| async { decks.get("THIRD").await.is_some() }).await;
+
+        // The lost deck wakes the supervisor up.
+        devices.unplug("virtual");
+        let deck = Arc::new(VirtualDeck::new(Kind::Akp03));
+        devices.plug(deck.clone());
+
+        // The deck comes back on the page it was left on.
+        eventually(|| async { deck.image(0).is_some() }).await;
+        let state = decks.get("virtual").await.unwrap();
+        assert_eq!(state.navigation.read().await.page, "second");
+
+        supervisor.abort();
+    }
+}
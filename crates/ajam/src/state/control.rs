@@ -0,0 +1,173 @@
+use serde_json::Value;
+
+use crate::control::{Call, ConnectionInfo, ControlError, DeckInfo, StatusInfo};
+
+use super::events::LazyPerformer;
+use super::sequence::Input;
+use super::navigation::{NavigationError, Navigator};
+use super::reload::StateReload;
+use super::render::{RenderError, StateRender};
+use super::{Decks, State};
+
+pub(crate) trait StateControl {
+    async fn handle_call(&self, call: Call) -> Result<Value, ControlError>;
+}
+
+impl StateControl for State {
+    async fn handle_call(&self, call: Call) -> Result<Value, ControlError> {
+        match call {
+            Call::Status => {}
+            Call::Navigate { profile, page } => {
+                let result = match profile {
+                    Some(profile) => self.navigate_to(&profile, &page).await,
+                    None => self.navigate_to_page(&page).await,
+                };
+                match result {
+                    Err(NavigationError::RenderError(RenderError::NoDevice)) => {}
+                    result => result?,
+                }
+            }
+            Call::SetBrightness { brightness } => {
+                match self.set_brightness_level(brightness).await {
+                    Err(RenderError::NoDevice) => {}
+                    result => result?,
+                }
+            }
+            Call::Press { key } => {
+                let Some(button) = self.get_button(key).await else {
+                    return Err(ControlError::NoButton(key));
+                };
+                let mut performer = LazyPerformer::default();
+                if button.toggle().is_some() {
+                    self.press_toggle(key, &button, &mut performer).await;
+                } else {
+                    let action = button.action(false).clone();
+                    self.execute_input_action(Input::Button(key), action, &mut performer, true).await;
+                }
+            }
+            Call::Reload => self.reload_profiles(&[]).await?,
+            Call::Connection => {
+                let decks: Vec<DeckInfo> = self.deck_info().await.into_iter().collect();
+                return Ok(serde_json::to_value(connection_info(decks))?);
+            }
+        }
+
+        Ok(serde_json::to_value(self.status().await)?)
+    }
+}
+
+impl Decks {
+    /// Handles a call for the deck with the serial number, the first deck if it is `None`.
+    /// Reloads apply to every deck.
+    pub async fn handle_call(&self, deck: Option<&str>, call: Call) -> Result<Value, ControlError> {
+        let state = match deck {
+            Some(serial) => self
+                .get(serial)
+                .await
+                .ok_or_else(|| ControlError::NoDeck(serial.to_string()))?,
+            None => self.primary().await,
+        };
+
+        match call {
+            Call::Reload => {
+                self.reload_profiles(&[]).await?;
+                Ok(serde_json::to_value(state.status().await)?)
+            }
+            Call::Connection => {
+                let mut decks = Vec::new();
+                for state in self.states().await {
+                    decks.extend(state.deck_info().await);
+                }
+                Ok(serde_json::to_value(connection_info(decks))?)
+            }
+            call => state.handle_call(call).await,
+        }
+    }
+}
+
+fn connection_info(decks: Vec<DeckInfo>) -> ConnectionInfo {
+    ConnectionInfo {
+        connected: !decks.is_empty(),
+        device: decks.first().map(|deck| deck.device.clone()),
+        decks,
+    }
+}
+
+impl State {
+    async fn deck_info(&self) -> Option<DeckInfo> {
+        let (kind, serial) = self.deck_id().await?;
+        Some(DeckInfo {
+            serial,
+            device: format!("{:?}", kind),
+        })
+    }
+
+    async fn status(&self) -> StatusInfo {
+        let navigation = self.navigation.read().await.clone();
+        StatusInfo {
+            profile: navigation.profile,
+            page: navigation.page,
+            brightness: self.brightness.load(std::sync::atomic::Ordering::Relaxed),
+            connected: self.dev.read().await.is_some(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::deck::DeckCall;
+    use crate::state::testing::attached_state;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_navigate_and_status() {
+        let (state, _deck, _dir) = attached_state().await;
+
+        let call = Call::Navigate {
+            profile: Some("common".to_string()),
+            page: "second".to_string(),
+        };
+        let status: StatusInfo =
+            serde_json::from_value(state.handle_call(call).await.unwrap()).unwrap();
+
+        assert_eq!(status.profile, "common");
+        assert_eq!(status.page, "second");
+    }
+
+    #[tokio::test]
+    async fn test_set_brightness() {
+        let (state, deck, _dir) = attached_state().await;
+        deck.take_calls();
+
+        let call = Call::SetBrightness { brightness: 150 };
+        let status: StatusInfo =
+            serde_json::from_value(state.handle_call(call).await.unwrap()).unwrap();
+
+        assert_eq!(status.brightness, 100);
+        assert_eq!(deck.calls(), vec![DeckCall::SetBrightness(100)]);
+    }
+
+    #[tokio::test]
+    async fn test_press() {
+        let (state, _deck, _dir) = attached_state().await;
+
+        state.handle_call(Call::Press { key: 0 }).await.unwrap();
+        assert_eq!(state.navigation.read().await.page, "second");
+
+        let result = state.handle_call(Call::Press { key: 5 }).await;
+        assert!(matches!(result, Err(ControlError::NoButton(5))));
+    }
+
+    #[tokio::test]
+    async fn test_connection() {
+        let (state, _deck, _dir) = attached_state().await;
+
+        let info: ConnectionInfo =
+            serde_json::from_value(state.handle_call(Call::Connection).await.unwrap()).unwrap();
+
+        assert!(info.connected);
+        assert_eq!(info.device.as_deref(), Some("Akp03"));
+        assert_eq!(info.decks[0].serial, "virtual");
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{mpsc, Arc};
+
+use ajam_activity::Event;
+use ajam_profile::Page;
+use colored::Colorize;
+use tokio::sync::{Notify, RwLock};
+use tokio::task::{self, JoinHandle};
+
+use crate::config::DeviceFilter;
+use crate::deck::{DeckError, SharedDeck};
+use crate::{print_debug, print_error, print_info};
+
+use super::activity::ActivityHandler;
+use super::animate::StateAnimate;
+use super::connect::StateConnect;
+use super::navigation::Location;
+use super::render::StateRender;
+use super::State;
+
+/// Focus is the focused app and the title of its window.
+type Focus = (String, Option<String>);
+
+/// Decks are the connected decks, each one with a state of its own.
+///
+/// The states share the profiles, so reloads and app changes apply to every deck.
+#[derive(Clone)]
+pub(crate) struct Decks {
+    /// Shared is the state deck states are created from, it is never attached.
+    shared: State,
+    decks: Arc<RwLock<BTreeMap<String, AttachedDeck>>>,
+    /// Lost are the states of decks that went away, restored when they come back.
+    lost: Arc<RwLock<HashMap<String, State>>>,
+    /// Focus is kept to show the right profile on decks attached later.
+    focus: Arc<RwLock<Focus>>,
+    /// Filter selects the devices connected to.
+    filter: Arc<DeviceFilter>,
+    /// DeviceAdded is notified when a HID device is connected, devices are searched for then.
+    device_added: Arc<Notify>,
+}
+
+/// AttachedDeck is the state of a deck and the tasks running for it.
+struct AttachedDeck {
+    state: State,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl AttachedDeck {
+    fn stop(&self) {
+        for task in self.tasks.iter() {
+            task.abort();
+        }
+    }
+}
+
+impl Decks {
+    pub fn new(shared: State) -> Self {
+        Self {
+            shared,
+            decks: Arc::new(RwLock::new(BTreeMap::new())),
+            lost: Arc::new(RwLock::new(HashMap::new())),
+            focus: Arc::new(RwLock::new(Focus::default())),
+            filter: Arc::new(DeviceFilter::default()),
+            device_added: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Connects only to the devices selected by the filter.
+    pub fn with_device_filter(mut self, filter: DeviceFilter) -> Self {
+        self.filter = Arc::new(filter);
+        self
+    }
+
+    pub(super) fn device_filter(&self) -> &DeviceFilter {
+        &self.filter
+    }
+
+    pub(super) fn device_added(&self) -> &Notify {
+        &self.device_added
+    }
+
+    /// Creates decks with an attached state, its tasks are left to the caller.
+    #[cfg(test)]
+    pub async fn with_state(state: State) -> Self {
+        let decks = Self::new(state.clone());
+        let serial = state.deck_id().await.map(|(_, serial)| serial).unwrap_or_default();
+        let deck = AttachedDeck {
+            state,
+            tasks: Vec::new(),
+        };
+        decks.decks.write().await.insert(serial, deck);
+        decks
+    }
+
+    pub(super) fn shared(&self) -> &State {
+        &self.shared
+    }
+
+    /// Returns the states of the attached decks ordered by serial number.
+    pub async fn states(&self) -> Vec<State> {
+        let decks = self.decks.read().await;
+        decks.values().map(|deck| deck.state.clone()).collect()
+    }
+
+    /// Returns the state of the deck with the serial number.
+    pub async fn get(&self, serial: &str) -> Option<State> {
+        let decks = self.decks.read().await;
+        decks.get(serial).map(|deck| deck.state.clone())
+    }
+
+    /// Returns the state of the first deck, or the shared one when none is attached.
+    pub async fn primary(&self) -> State {
+        let decks = self.decks.read().await;
+        match decks.values().next() {
+            Some(deck) => deck.state.clone(),
+            None => self.shared.clone(),
+        }
+    }
+
+    /// Attaches a deck with a state of its own and starts its tasks.
+    ///
+    /// A deck that was lost gets its previous state back.
+    pub async fn attach(&self, deck: SharedDeck) {
+        let serial = deck.serial();
+        print_info!("Attaching {:?} deck {}", deck.kind(), serial);
+        let lost = self.lost.write().await.remove(&serial);
+        let state = match lost {
+            Some(state) => state,
+            None => self.shared.for_deck(&serial).await,
+        };
+        state.attach_deck(deck).await;
+
+        let (bundle_id, title) = self.focus.read().await.clone();
+        let context = self.shared.condition_context().await;
+        state.show_app_profile(&bundle_id, title.as_deref(), &context).await;
+
+        let deck = AttachedDeck {
+            tasks: spawn_deck_tasks(&state),
+            state,
+        };
+        if let Some(previous) = self.decks.write().await.insert(serial, deck) {
+            previous.stop();
+        }
+    }
+
+    /// Forgets a deck that is gone, stopping its tasks. Its state is kept for reconnection.
+    pub async fn detach(&self, serial: &str) {
+        let Some(deck) = self.decks.write().await.remove(serial) else {
+            return;
+        };
+        print_info!("Detaching deck {}", serial);
+        deck.stop();
+        deck.state.detach_deck().await;
+        self.lost.write().await.insert(serial.to_string(), deck.state);
+    }
+
+    /// Returns the pages shown on the decks, a page shown on several decks is listed once.
+    pub(super) async fn active_pages(&self) -> HashMap<Location, Page> {
+        let mut pages = HashMap::new();
+        for state in self.states().await {
+            let location = state.navigation.read().await.location();
+            if pages.contains_key(&location) {
+                continue;
+            }
+            let page = state.get_page(&location.profile, &location.page).await;
+            if let Some((_profile, page)) = page {
+                pages.insert(location, page);
+            }
+        }
+        pages
+    }
+
+    /// Renders the decks showing one of the pages again.
+    pub(super) async fn render_pages(&self, locations: &HashSet<Location>) {
+        if locations.is_empty() {
+            return;
+        }
+        for state in self.states().await {
+            let location = state.navigation.read().await.location();
+            if !locations.contains(&location) {
+                continue;
+            }
+            if let Err(e) = state.render_active_page().await {
+                print_debug!("Failed to render updated page: {}", e);
+            }
+        }
+    }
+
+    /// Shows the profile of the focused app and the pages whose condition holds on every deck.
+    pub(super) async fn show_focused_profiles(&self) {
+        let (bundle_id, title) = self.focus.read().await.clone();
+        let context = self.shared.condition_context().await;
+        for state in self.states().await {
+            state.show_app_profile(&bundle_id, title.as_deref(), &context).await;
+        }
+    }
+
+    /// Clears and disconnects every deck.
+    pub async fn disconnect_all(&self) -> Result<(), DeckError> {
+        for state in self.states().await {
+            state.disconnect_deck().await?;
+        }
+        Ok(())
+    }
+}
+
+fn spawn_deck_tasks(state: &State) -> Vec<JoinHandle<()>> {
+    let animations = state.clone();
+    vec![task::spawn(async move { animations.watch_animations().await })]
+}
+
+impl ActivityHandler for Decks {
+    /// Handles the events for every deck, conditions are re-evaluated after every one of them.
+    async fn listen_activity_events(&self, rx: mpsc::Receiver<Event>) {
+        while let Ok(event) = rx.recv() {
+            let mut rerender = false;
+            match event {
+                Event::AppChange(app) => {
+                    let mut focus = self.focus.write().await;
+                    if focus.0 == app {
+                        continue;
+                    }
+                    *focus = (app, None);
+                }
+                Event::WindowTitleChange(app, title) => {
+                    // The title may arrive before the change of its app.
+                    *self.focus.write().await = (app, Some(title));
+                }
+                Event::HidDeviceAdded => {
+                    self.device_added.notify_one();
+                    continue;
+                }
+                Event::DisplaysChange(displays) => {
+                    print_debug!("Displays changed: {:?}", displays);
+                    self.shared.set_displays(displays).await;
+                }
+                Event::AudioOutputChange(device_name) => {
+                    print_debug!("Audio output changed: {}", device_name);
+                    self.shared.set_audio_output_device(&device_name).await;
+                    rerender = true;
+                }
+                Event::AudioInputChange(device_name) => {
+                    print_debug!("Audio input changed: {}", device_name);
+                    self.shared.set_audio_input_device(&device_name).await;
+                    rerender = true;
+                }
+            }
+
+            if rerender {
+                for state in self.states().await {
+                    if let Err(e) = state.render_active_page().await {
+                        print_error!("error rendering active page: {:?}", e);
+                    }
+                }
+            }
+            self.show_focused_profiles().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ajam_profile::open_profiles;
+    use ajazz_sdk::info::Kind;
+
+    use crate::deck::VirtualDeck;
+    use crate::state::testing::{write_profile, TempDir, TEST_MANIFEST};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_decks_show_their_profiles() {
+        let dir = TempDir::new();
+        write_profile(dir.path(), "common", TEST_MANIFEST, &["a.bmp", "b.bmp"]);
+        let manifest = TEST_MANIFEST.replace("device: akp03", "device: akp153");
+        write_profile(dir.path(), "common-akp153", &manifest, &["a.bmp", "b.bmp"]);
+        let pinned = TEST_MANIFEST.replace("device: akp03", "device: akp153:RIGHT");
+        write_profile(dir.path(), "com.apple.Safari", &pinned, &["a.bmp", "b.bmp"]);
+
+        let state = State::with_profiles(dir.path().to_path_buf(), open_profiles(dir.path()).unwrap());
+        let decks = Decks::new(state);
+        let small = Arc::new(VirtualDeck::new(Kind::Akp03).with_serial("SMALL"));
+        let left = Arc::new(VirtualDeck::new(Kind::Akp153).with_serial("LEFT"));
+        decks.attach(small.clone()).await;
+        decks.attach(left.clone()).await;
+
+        let profile = |serial: &'static str| {
+            let decks = decks.clone();
+            async move {
+                let state = decks.get(serial).await.unwrap();
+                let profile = state.navigation.read().await.profile.clone();
+                profile
+            }
+        };
+        assert_eq!(profile("SMALL").await, "common");
+        assert_eq!(profile("LEFT").await, "common-akp153");
+        assert!(small.image(0).is_some());
+        assert!(left.image(0).is_some());
+
+        let (tx, rx) = mpsc::channel();
+        tx.send(Event::AppChange("com.apple.Safari".to_string())).unwrap();
+        drop(tx);
+        decks.listen_activity_events(rx).await;
+        assert_eq!(profile("SMALL").await, "common");
+        assert_eq!(profile("LEFT").await, "common-akp153");
+
+        // Decks attached later show the profile of the focused app.
+        let right = Arc::new(VirtualDeck::new(Kind::Akp153).with_serial("RIGHT"));
+        decks.attach(right).await;
+        assert_eq!(profile("RIGHT").await, "com.apple.Safari");
+
+        decks.detach("LEFT").await;
+        assert_eq!(decks.states().await.len(), 2);
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use ajam_profile::{image_map_path, ButtonImage};
+use colored::Colorize;
+use tokio::task::JoinSet;
+use tokio::time::{interval, Instant};
+
+use crate::print_warning;
+
+use super::events::run_command;
+use super::navigation::Location;
+use super::toggle::PROBE_TIMEOUT;
+use super::Decks;
+
+const COMMAND_TICK: Duration = Duration::from_millis(250);
+
+/// CommandUse is a key showing the output of an image command.
+struct CommandUse {
+    location: Location,
+    images: HashMap<String, String>,
+}
+
+impl Decks {
+    /// Periodically runs the commands of images on the pages the decks show.
+    ///
+    /// Outputs are shared by command, so a command runs once for all the keys showing it,
+    /// at the shortest of their intervals. Only the decks showing a changed image are
+    /// rendered again.
+    pub async fn watch_image_commands(&self) {
+        let mut last_runs = HashMap::new();
+        let mut ticker = interval(COMMAND_TICK);
+
+        loop {
+            ticker.tick().await;
+            let changed = self.run_image_commands(&mut last_runs).await;
+            self.render_pages(&changed).await;
+        }
+    }
+
+    /// Runs the due image commands of the shown pages, returns the pages whose images changed.
+    ///
+    /// Due commands run concurrently and are given at most their interval to finish.
+    async fn run_image_commands(
+        &self,
+        last_runs: &mut HashMap<String, Instant>,
+    ) -> HashSet<Location> {
+        let shared = self.shared();
+        let mut commands: HashMap<String, (Duration, Vec<CommandUse>)> = HashMap::new();
+        for (location, page) in self.active_pages().await {
+            for (key, button) in page.indexed_buttons() {
+                let on = button.toggle().is_some() && shared.is_toggled_at(&location, key).await;
+                let Some(ButtonImage::Command {
+                    command,
+                    interval_ms,
+                    images,
+                }) = button.image(on)
+                else {
+                    continue;
+                };
+
+                let period = Duration::from_millis(*interval_ms);
+                let (shortest, uses) = commands
+                    .entry(command.clone())
+                    .or_insert_with(|| (period, Vec::new()));
+                *shortest = (*shortest).min(period);
+                uses.push(CommandUse {
+                    location: location.clone(),
+                    images: images.clone(),
+                });
+            }
+        }
+
+        let mut runs = JoinSet::new();
+        for (command, (period, uses)) in commands {
+            if let Some(last_run) = last_runs.get(&command) {
+                if last_run.elapsed() < period {
+                    continue;
+                }
+            }
+            last_runs.insert(command.clone(), Instant::now());
+
+            let limit = PROBE_TIMEOUT.min(period);
+            runs.spawn(async move {
+                let output = run_command(&command, Some(limit)).await;
+                (command, uses, output)
+            });
+        }
+
+        let mut changed = HashSet::new();
+        while let Some(run) = runs.join_next().await {
+            let Ok((command, uses, output)) = run else {
+                continue;
+            };
+            let output = match output {
+                Ok(output) => output.stdout.trim().to_string(),
+                Err(e) => {
+                    print_warning!("Image command failed, using default image: {}", e);
+                    String::new()
+                }
+            };
+
+            let previous = shared.command_outputs.write().await.insert(command, output.clone());
+            for command_use in uses {
+                let images = &command_use.images;
+                let previous_image = previous.as_deref().and_then(|out| image_map_path(images, out));
+                if previous.is_none() || previous_image != image_map_path(images, &output) {
+                    changed.insert(command_use.location);
+                }
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ajam_profile::open_profiles;
+    use ajazz_sdk::info::Kind;
+    use tokio::time::sleep;
+
+    use crate::deck::VirtualDeck;
+    use crate::state::testing::{attached_state_with, write_profile, TempDir};
+    use crate::state::State;
+
+    use super::*;
+
+    const COMMAND_MANIFEST: &str = r#"
+pages_order:
+  - main
+device: akp03
+pages:
+  main:
+    0:
+      image:
+        command: "cat status"
+        interval_ms: 300
+        images:
+          ok: a.bmp
+          default: b.bmp
+      action:
+        command: "true"
+encoders: {}
+"#;
+
+    /// Creates decks with a deck showing the manifest as the common profile.
+    async fn command_decks(manifest: &str) -> (Decks, Arc<VirtualDeck>, TempDir) {
+        let (state, deck, dir) = attached_state_with(manifest).await;
+        (Decks::with_state(state).await, deck, dir)
+    }
+
+    #[tokio::test]
+    async fn test_command_image() {
+        let status_dir = TempDir::new();
+        let status_path = status_dir.path().join("status");
+        std::fs::write(&status_path, "ok\n").unwrap();
+        let command = format!("cat {}", status_path.display());
+        let manifest = COMMAND_MANIFEST.replace("cat status", &command);
+
+        let (decks, deck, _dir) = command_decks(&manifest).await;
+        let default_image = deck.image(0).unwrap();
+        let main = Location::new("common", "main");
+
+        let mut last_runs = HashMap::new();
+        let changed = decks.run_image_commands(&mut last_runs).await;
+        assert!(changed.contains(&main));
+        decks.render_pages(&changed).await;
+        assert_ne!(deck.image(0).unwrap(), default_image);
+
+        sleep(Duration::from_millis(350)).await;
+        assert!(decks.run_image_commands(&mut last_runs).await.is_empty());
+
+        std::fs::write(&status_path, "failing\n").unwrap();
+        sleep(Duration::from_millis(350)).await;
+        let changed = decks.run_image_commands(&mut last_runs).await;
+        assert!(changed.contains(&main));
+        decks.render_pages(&changed).await;
+        assert_eq!(deck.image(0).unwrap(), default_image);
+    }
+
+    #[tokio::test]
+    async fn test_command_image_non_zero_exit() {
+        let manifest = COMMAND_MANIFEST.replace("cat status", "echo ok; exit 1");
+        let (decks, deck, _dir) = command_decks(&manifest).await;
+        let default_image = deck.image(0).unwrap();
+
+        let changed = decks.run_image_commands(&mut HashMap::new()).await;
+        decks.render_pages(&changed).await;
+        assert_ne!(deck.image(0).unwrap(), default_image);
+    }
+
+    #[tokio::test]
+    async fn test_command_image_timeout() {
+        let manifest = COMMAND_MANIFEST.replace("cat status", "sleep 5; echo ok");
+        let (decks, deck, _dir) = command_decks(&manifest).await;
+        let default_image = deck.image(0).unwrap();
+
+        let started = Instant::now();
+        let changed = decks.run_image_commands(&mut HashMap::new()).await;
+        assert!(started.elapsed() < PROBE_TIMEOUT);
+        decks.render_pages(&changed).await;
+        assert_eq!(deck.image(0).unwrap(), default_image);
+    }
+
+    #[tokio::test]
+    async fn test_shared_command_runs() {
+        let slow_button = r#"    1:
+      image:
+        command: "cat status"
+        interval_ms: 60000
+        images:
+          ok: a.bmp
+          default: b.bmp
+      action:
+        command: "true"
+encoders: {}
+"#;
+        let manifest = COMMAND_MANIFEST
+            .replace("encoders: {}\n", slow_button)
+            .replace("cat status", "echo ok");
+        let dir = TempDir::new();
+        write_profile(dir.path(), "common", &manifest, &["a.bmp", "b.bmp"]);
+        let state = State::with_profiles(dir.path().to_path_buf(), open_profiles(dir.path()).unwrap());
+        let decks = Decks::new(state);
+        let left = Arc::new(VirtualDeck::new(Kind::Akp03).with_serial("LEFT"));
+        let right = Arc::new(VirtualDeck::new(Kind::Akp03).with_serial("RIGHT"));
+        decks.attach(left.clone()).await;
+        decks.attach(right.clone()).await;
+        let default_image = left.image(0).unwrap();
+
+        // Both decks show the page, the command runs once for both keys of it.
+        let mut last_runs = HashMap::new();
+        let changed = decks.run_image_commands(&mut last_runs).await;
+        assert_eq!(last_runs.len(), 1);
+        let first_run = last_runs["echo ok"];
+        decks.render_pages(&changed).await;
+        assert_ne!(left.image(0).unwrap(), default_image);
+        assert_ne!(right.image(0).unwrap(), default_image);
+
+        // The command runs at the shortest interval of its keys.
+        sleep(Duration::from_millis(350)).await;
+        decks.run_image_commands(&mut last_runs).await;
+        assert!(last_runs["echo ok"] > first_run);
+    }
+}
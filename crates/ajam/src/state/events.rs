@@ -1,34 +1,82 @@
+use std::process::ExitStatus;
 use std::sync::Arc;
+use std::time::Duration;
 
 use ajam_keypress::Performer;
-use ajam_profile::{Action, EncoderActions};
-use ajazz_sdk::asynchronous::AsyncDeviceStateReader;
+use ajam_profile::{Action, Button, EncoderActions, Navigation, NavigationCommand};
 use ajazz_sdk::DeviceStateUpdate;
+use enigo::InputResult;
 use tokio::process::Command;
+use tokio::sync::mpsc;
+use tokio::task;
+use tokio::time::{sleep, sleep_until, timeout, Instant};
 
+use crate::deck::DeckReader;
 use crate::state::render::StateRender;
 use crate::state::State;
 use crate::{print_debug, print_error, print_warning};
 use colored::Colorize;
 
+use super::gestures::{Gesture, GestureConfig, GestureTracker};
+use super::sequence::{ActionError, Input};
 use super::navigation::{NavigationError, Navigator};
 
-const KEY_PREVIOUS: u8 = 6;
-const KEY_HOME: u8 = 7;
-const KEY_NEXT: u8 = 8;
+/// LazyPerformer creates the input performer on first use,
+/// so decks without key actions work without accessibility access.
+#[derive(Default)]
+pub(crate) struct LazyPerformer(Option<Performer>);
+
+impl LazyPerformer {
+    pub fn get(&mut self) -> Option<&mut Performer> {
+        if self.0.is_none() {
+            match Performer::new() {
+                Ok(performer) => self.0 = Some(performer),
+                Err(e) => {
+                    print_error!("failed to create performer: {:?}", e);
+                    return None;
+                }
+            }
+        }
+        self.0.as_mut()
+    }
+}
 
 impl State {
+    /// Returns the navigation keys of the active profile.
+    ///
+    /// Profiles without navigation config use the one of the common profile,
+    /// falling back to the device layout.
+    async fn get_navigation(&self) -> Option<Navigation> {
+        let profile_name = self.navigation.read().await.profile.clone();
+        let default = self.default_profile().await;
+        let profiles = self.profiles.read().await;
+        let configured = [profile_name.as_str(), default.as_str()]
+            .iter()
+            .find_map(|name| profiles.get(*name)?.manifest.navigation.clone());
+        if configured.is_some() {
+            return configured;
+        }
+
+        let dev = self.dev.read().await.clone()?;
+        Some(Navigation::default_for(dev.kind()))
+    }
+
+    /// Handles navigation keys that the active page leaves unassigned.
     async fn handle_navigation_buttons(&self, key: u8) -> Result<Option<()>, NavigationError> {
-        match key {
-            KEY_PREVIOUS => self.navigate_to_previous_page().await?,
-            KEY_NEXT => self.navigate_to_next_page().await?,
-            KEY_HOME => self.toggle_home().await?,
-            _ => return Ok(None),
+        if let Some((_profile, page)) = self.get_active_page().await {
+            if page.get_button(key).is_some() {
+                return Ok(None);
+            }
+        }
+        let Some(command) = self.get_navigation().await.and_then(|n| n.command(key)) else {
+            return Ok(None);
         };
+
+        self.run_navigation_command(command).await?;
         Ok(Some(()))
     }
 
-    async fn get_button_action(&self, key: u8) -> Option<Action> {
+    pub(super) async fn get_button(&self, key: u8) -> Option<Button> {
         let Some((_profile, page)) = self.get_active_page().await else {
             print_warning!("no active page found");
             return None;
@@ -39,7 +87,7 @@ impl State {
             return None;
         };
 
-        Some(button.action.clone())
+        Some(button.clone())
     }
 
     async fn get_encoder_actions(&self, dial: u8) -> Option<EncoderActions> {
@@ -57,134 +105,497 @@ impl State {
         }
     }
 
-    async fn execute_action(&self, action: Action, performer: &mut Performer, release: bool) {
+    pub(super) async fn execute_action(&self, action: Action, performer: &mut LazyPerformer, release: bool) {
+        if let Err(e) = self.perform_action(action, performer, release).await {
+            print_error!("error performing action: {}", e);
+        }
+    }
+
+    pub(super) async fn perform_action(
+        &self,
+        action: Action,
+        performer: &mut LazyPerformer,
+        release: bool,
+    ) -> Result<(), ActionError> {
         match action {
             Action::Keys { keys } => {
-                if let Err(e) = {
-                    if release {
-                        performer.perform(&keys)
+                let Some(performer) = performer.get() else {
+                    return Err(ActionError::NoPerformer);
+                };
+                if release {
+                    performer.perform(&keys)?;
+                } else {
+                    performer.press(&keys)?;
+                }
+            }
+            Action::Command { command } => {
+                let output = run_command(&command, None).await.map_err(ActionError::Command)?;
+                if !output.status.success() {
+                    print_warning!("Command {:?} exited with {}", command, output.status);
+                }
+            }
+            Action::Navigate { navigate } => match NavigationCommand::parse(&navigate) {
+                Some(command) => self.run_navigation_command(command).await?,
+                None => self.navigate_to_page(&navigate).await?,
+            },
+            Action::Type { text, char_delay_ms } => {
+                let Some(performer) = performer.get() else {
+                    return Err(ActionError::NoPerformer);
+                };
+                type_text(performer, &text, char_delay_ms).await?;
+            }
+            Action::Sequence { sequence, repeat } => {
+                self.run_sequence(&sequence, repeat, performer).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Executes the action of a button or encoder. Sequences and typing with a delay run
+    /// in the background, using the same input again while one runs cancels it.
+    pub(super) async fn execute_input_action(
+        &self,
+        input: Input,
+        action: Action,
+        performer: &mut LazyPerformer,
+        release: bool,
+    ) {
+        if runs_in_background(&action) {
+            self.start_action(input, action).await;
+        } else {
+            self.execute_action(action, performer, release).await;
+        }
+    }
+
+    async fn handle_gesture(
+        &self,
+        key: u8,
+        gesture: Gesture,
+        button: Button,
+        performer: &mut LazyPerformer,
+    ) {
+        print_debug!("button {} gesture: {:?}", key, gesture);
+        let action = match gesture {
+            Gesture::Tap if button.toggle().is_some() => {
+                self.press_toggle(key, &button, performer).await;
+                None
+            }
+            Gesture::Tap => Some(button.action(false).clone()),
+            Gesture::LongPress => button.long_press.map(|long_press| long_press.action),
+            Gesture::DoubleTap => button.double_tap.map(|double_tap| double_tap.action),
+        };
+        if let Some(action) = action {
+            self.execute_input_action(Input::Button(key), action, performer, true).await;
+        }
+    }
+
+    async fn handle_update(
+        &self,
+        update: DeviceStateUpdate,
+        gestures: &mut GestureTracker<Button>,
+        performer: &mut LazyPerformer,
+    ) {
+        match update {
+            DeviceStateUpdate::ButtonDown(key) => {
+                match self.handle_navigation_buttons(key).await {
+                    Ok(None) => {}
+                    Ok(Some(_)) => {
+                        return;
+                    }
+                    Err(e) => {
+                        print_error!("error navigating: {:?}", e);
+                        return;
+                    }
+                }
+
+                let Some(button) = self.get_button(key).await else {
+                    return;
+                };
+
+                if !button.has_gestures() {
+                    if button.toggle().is_some() {
+                        self.press_toggle(key, &button, performer).await;
                     } else {
-                        performer.press(&keys)
+                        let action = button.action(false).clone();
+                        self.execute_input_action(Input::Button(key), action, performer, false).await;
                     }
-                } {
-                    print_error!("error pressing key: {:?}", e);
+                    return;
+                }
+
+                let config = GestureConfig::from(&button);
+                let now = Instant::now();
+                if let Some((gesture, button)) = gestures.press(key, now, config, button) {
+                    self.handle_gesture(key, gesture, button, performer).await;
                 }
             }
-            Action::Command { command } => {
-                if let Err(e) = run_command(&command).await {
-                    print_error!("error running command: {:?}", e);
+            DeviceStateUpdate::ButtonUp(key) => {
+                if gestures.is_tracking(key) {
+                    if let Some((gesture, button)) = gestures.release(key, Instant::now()) {
+                        self.handle_gesture(key, gesture, button, performer).await;
+                    }
+                    return;
+                }
+
+                let Some(button) = self.get_button(key).await else {
+                    return;
+                };
+
+                if let (None, Action::Keys { keys }) = (button.toggle(), button.action(false)) {
+                    let Some(performer) = performer.get() else {
+                        return;
+                    };
+                    if let Err(e) = performer.release(keys) {
+                        print_error!("error releasing key: {:?}", e);
+                    }
                 }
             }
-            Action::Navigate { navigate } => {
-                if let Err(e) = self.navigate_to_page(&navigate).await {
-                    print_error!("error navigating to page: {:?}", e);
+            DeviceStateUpdate::EncoderTwist(dial, ticks) => {
+                let Some(encoder_actions) = self.get_encoder_actions(dial).await else {
+                    return;
+                };
+
+                let action = if ticks > 0 {
+                    encoder_actions.plus
+                } else {
+                    encoder_actions.minus
+                };
+
+                if let Action::Keys { keys } = &action {
+                    if keys.is_illumination() {
+                        if let Err(e) = self.set_brightness(ticks.saturating_mul(self.config.brightness.step as i8)).await {
+                            print_error!("error setting brightness: {:?}", e);
+                        }
+                        return;
+                    }
                 }
+
+                self.execute_input_action(Input::Encoder(dial), action, performer, true).await;
+            }
+            DeviceStateUpdate::EncoderDown(dial) => {
+                let Some(encoder_actions) = self.get_encoder_actions(dial).await else {
+                    return;
+                };
+
+                let Some(action) = encoder_actions.click else {
+                    print_warning!("no click action found");
+                    return;
+                };
+
+                self.execute_input_action(Input::Encoder(dial), action, performer, true).await;
+            }
+            DeviceStateUpdate::EncoderUp(dial) => {
+                print_debug!("encoder {} released", dial);
             }
         }
     }
 }
+
 pub trait StateEventsHandler {
-    async fn listen_device_events(&self, dev_reader: Arc<AsyncDeviceStateReader>);
+    async fn listen_device_events(&self, dev_reader: Arc<dyn DeckReader>);
+}
+
+/// AbortOnDrop aborts the task when dropped, so it does not outlive a cancelled owner.
+struct AbortOnDrop(task::JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
 }
 
 impl StateEventsHandler for State {
-    async fn listen_device_events(&self, dev_reader: Arc<AsyncDeviceStateReader>) {
-        let Ok(mut performer) = Performer::new() else {
-            print_error!("failed to create performer");
-            return;
-        };
+    async fn listen_device_events(&self, dev_reader: Arc<dyn DeckReader>) {
+        let mut performer = LazyPerformer::default();
+        let mut gestures = GestureTracker::default();
+
+        // Reads run in their own task so pending gestures can fire while waiting for input.
+        let (updates_tx, mut updates_rx) = mpsc::unbounded_channel();
+        let _reader_task = AbortOnDrop(task::spawn(async move {
+            loop {
+                let result = dev_reader.read().await;
+                let failed = result.is_err();
+                if updates_tx.send(result).is_err() || failed {
+                    break;
+                }
+            }
+        }));
 
         loop {
-            match dev_reader.read(100.0).await {
-                Ok(updates) => {
+            let received = match gestures.next_deadline() {
+                Some(deadline) => tokio::select! {
+                    received = updates_rx.recv() => Some(received),
+                    _ = sleep_until(deadline) => None,
+                },
+                None => Some(updates_rx.recv().await),
+            };
+
+            for (key, gesture, button) in gestures.poll(Instant::now()) {
+                self.handle_gesture(key, gesture, button, &mut performer).await;
+            }
+
+            match received {
+                None => {}
+                Some(Some(Ok(updates))) => {
                     for update in updates {
-                        match update {
-                            DeviceStateUpdate::ButtonDown(key) => {
-                                match self.handle_navigation_buttons(key).await {
-                                    Ok(None) => {}
-                                    Ok(Some(_)) => {
-                                        continue;
-                                    }
-                                    Err(e) => {
-                                        print_error!("error navigating: {:?}", e);
-                                        continue;
-                                    }
-                                }
-
-                                let Some(action) = self.get_button_action(key).await else {
-                                    continue;
-                                };
-
-                                self.execute_action(action, &mut performer, false).await;
-                            }
-                            DeviceStateUpdate::ButtonUp(key) => {
-                                let Some(action) = self.get_button_action(key).await else {
-                                    continue;
-                                };
-
-                                if let Action::Keys { keys } = &action {
-                                    if let Err(e) = performer.release(keys) {
-                                        print_error!("error releasing key: {:?}", e);
-                                    }
-                                }
-                            }
-                            DeviceStateUpdate::EncoderTwist(dial, ticks) => {
-                                let Some(encoder_actions) = self.get_encoder_actions(dial).await
-                                else {
-                                    continue;
-                                };
-
-                                let action = if ticks > 0 {
-                                    encoder_actions.plus
-                                } else {
-                                    encoder_actions.minus
-                                };
-
-                                if let Action::Keys { keys } = &action {
-                                    if keys.is_illumination() {
-                                        if let Err(e) = self.set_brightness(ticks * 5).await {
-                                            print_error!("error setting brightness: {:?}", e);
-                                        }
-                                        continue;
-                                    }
-                                }
-
-                                self.execute_action(action, &mut performer, true).await;
-                            }
-                            DeviceStateUpdate::EncoderDown(dial) => {
-                                let Some(encoder_actions) = self.get_encoder_actions(dial).await else {
-                                    continue;
-                                };
-
-                                let Some(action) = encoder_actions.click else {
-                                    print_warning!("no click action found");
-                                    continue;
-                                };
-
-                                self.execute_action(action, &mut performer, true).await;
-                            }
-                            DeviceStateUpdate::EncoderUp(dial) => {
-                                print_debug!("encoder {} released", dial);
-                            }
-                        }
+                        self.handle_update(update, &mut gestures, &mut performer).await;
                     }
                 }
-                Err(e) => {
+                Some(Some(Err(e))) => {
                     print_error!("error reading device events: {}", e);
                     break;
                 }
+                Some(None) => break,
             }
         }
     }
 }
 
-pub(crate) async fn run_command(command: &str) -> Result<String, String> {
+/// Returns whether the action takes long enough to block input while it runs.
+pub(super) fn runs_in_background(action: &Action) -> bool {
+    match action {
+        Action::Sequence { .. } => true,
+        Action::Type { char_delay_ms, .. } => *char_delay_ms > 0,
+        _ => false,
+    }
+}
+
+async fn type_text(performer: &mut Performer, text: &str, char_delay_ms: u64) -> InputResult<()> {
+    if char_delay_ms == 0 {
+        return performer.text(text);
+    }
+
+    let delay = Duration::from_millis(char_delay_ms);
+    let mut buffer = [0; 4];
+    for ch in text.chars() {
+        performer.text(ch.encode_utf8(&mut buffer))?;
+        sleep(delay).await;
+    }
+    Ok(())
+}
+
+/// CommandOutput is the standard output and exit status of a shell command.
+pub(crate) struct CommandOutput {
+    pub stdout: String,
+    pub status: ExitStatus,
+}
+
+/// Runs the command with `sh`, it is killed if it does not finish in time.
+/// The output is returned whatever the exit status is.
+pub(crate) async fn run_command(
+    command: &str,
+    limit: Option<Duration>,
+) -> Result<CommandOutput, String> {
     print_debug!("running command: {:?}", command);
-    let output = Command::new("sh")
+    let running = Command::new("sh")
         .arg("-c")
         .arg(command)
-        .output()
-        .await
-        .expect("failed to run command");
-    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        .kill_on_drop(true)
+        .output();
+    let output = match limit {
+        Some(limit) => timeout(limit, running)
+            .await
+            .map_err(|_| format!("'{}' did not finish in {:?}", command, limit))?,
+        None => running.await,
+    };
+    let output = output.map_err(|e| format!("failed to run command: {}", e))?;
+    Ok(CommandOutput {
+        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+        status: output.status,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use crate::deck::DeckCall;
+    use crate::state::testing::{attached_state, attached_state_with, eventually, TEST_MANIFEST};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_button_navigates() {
+        let (state, deck, _dir) = attached_state().await;
+        deck.take_calls();
+
+        deck.send(DeviceStateUpdate::ButtonDown(0));
+        deck.send(DeviceStateUpdate::ButtonUp(0));
+
+        let (state, deck) = (&state, &deck);
+        eventually(|| async move { state.navigation.read().await.page == "second" }).await;
+        eventually(|| async move { deck.calls().contains(&DeckCall::Flush) }).await;
+        assert!(deck.calls().contains(&DeckCall::ClearButtonImage(1)));
+    }
+
+    #[tokio::test]
+    async fn test_buttons_without_display() {
+        let manifest = TEST_MANIFEST.replace(
+            "  second:\n",
+            "    6:\n      action:\n        navigate: second\n  second:\n",
+        );
+        let (state, deck, _dir) = attached_state_with(&manifest).await;
+        let state = &state;
+
+        // Key 6 is the first key without a display, the page overrides its built-in action.
+        deck.send(DeviceStateUpdate::ButtonDown(6));
+        eventually(|| async move { state.navigation.read().await.page == "second" }).await;
+
+        // The third one navigates to the next page by default.
+        deck.send(DeviceStateUpdate::ButtonDown(8));
+        eventually(|| async move { state.navigation.read().await.page == "main" }).await;
+    }
+
+    #[tokio::test]
+    async fn test_navigation_config() {
+        let manifest = TEST_MANIFEST
+            .replace("device: akp03\n", "device: akp03\nnavigation:\n  next: 7\n")
+            .replace("navigate: main", "navigate: \"@back\"");
+        let (state, deck, _dir) = attached_state_with(&manifest).await;
+        let state = &state;
+
+        deck.send(DeviceStateUpdate::ButtonDown(0));
+        eventually(|| async move { state.navigation.read().await.page == "second" }).await;
+        deck.send(DeviceStateUpdate::ButtonDown(0));
+        eventually(|| async move { state.navigation.read().await.page == "main" }).await;
+
+        deck.send(DeviceStateUpdate::ButtonDown(6));
+        deck.send(DeviceStateUpdate::ButtonDown(7));
+        eventually(|| async move { state.navigation.read().await.page == "second" }).await;
+    }
+
+    #[tokio::test]
+    async fn test_navigation_opt_out() {
+        let manifest =
+            TEST_MANIFEST.replace("device: akp03\n", "device: akp03\nnavigation:\n  enabled: false\n");
+        let (state, deck, _dir) = attached_state_with(&manifest).await;
+        deck.take_calls();
+
+        deck.send(DeviceStateUpdate::ButtonDown(8));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert_eq!(state.navigation.read().await.page, "main");
+        assert!(deck.calls().is_empty());
+    }
+
+    const GESTURES_MANIFEST: &str = r#"
+pages_order:
+  - main
+  - second
+  - third
+device: akp03
+pages:
+  main:
+    0:
+      image:
+        src: a.bmp
+      action:
+        command: "true"
+      long_press:
+        action:
+          navigate: third
+        threshold_ms: 50
+    1:
+      image:
+        src: b.bmp
+      action:
+        navigate: second
+      double_tap:
+        action:
+          navigate: third
+        interval_ms: 100
+  second: {}
+  third: {}
+encoders: {}
+"#;
+
+    #[tokio::test]
+    async fn test_long_press() {
+        let (state, deck, _dir) = attached_state_with(GESTURES_MANIFEST).await;
+
+        deck.send(DeviceStateUpdate::ButtonDown(0));
+
+        let state = &state;
+        eventually(|| async move { state.navigation.read().await.page == "third" }).await;
+    }
+
+    #[tokio::test]
+    async fn test_double_tap() {
+        let (state, deck, _dir) = attached_state_with(GESTURES_MANIFEST).await;
+
+        deck.send(DeviceStateUpdate::ButtonDown(1));
+        deck.send(DeviceStateUpdate::ButtonUp(1));
+        deck.send(DeviceStateUpdate::ButtonDown(1));
+        deck.send(DeviceStateUpdate::ButtonUp(1));
+
+        let state = &state;
+        eventually(|| async move { state.navigation.read().await.page == "third" }).await;
+    }
+
+    #[tokio::test]
+    async fn test_tap_waits_for_double_tap() {
+        let (state, deck, _dir) = attached_state_with(GESTURES_MANIFEST).await;
+
+        deck.send(DeviceStateUpdate::ButtonDown(1));
+        deck.send(DeviceStateUpdate::ButtonUp(1));
+        assert_eq!(state.navigation.read().await.page, "main");
+
+        let state = &state;
+        eventually(|| async move { state.navigation.read().await.page == "second" }).await;
+    }
+
+    #[tokio::test]
+    async fn test_encoder_sequence_runs_in_background() {
+        let manifest = TEST_MANIFEST.replace(
+            "    plus:\n      keys: illumination_up\n",
+            "    plus:\n      sequence:\n        - delay_ms: 5000\n        - navigate: main\n",
+        );
+        let (state, deck, _dir) = attached_state_with(&manifest).await;
+
+        deck.send(DeviceStateUpdate::EncoderTwist(0, 1));
+        deck.send(DeviceStateUpdate::ButtonDown(0));
+
+        let state = &state;
+        eventually(|| async move { state.navigation.read().await.page == "second" }).await;
+        assert!(state.background_actions.lock().await.contains_key(&Input::Encoder(0)));
+    }
+
+    #[tokio::test]
+    async fn test_slow_typing_runs_in_background() {
+        let manifest = TEST_MANIFEST.replace(
+            "        command: \"true\"\n",
+            "        type: hello\n        char_delay_ms: 1000\n",
+        );
+        let (state, _deck, _dir) = attached_state_with(&manifest).await;
+
+        let action = state.get_button(1).await.unwrap().action(false).clone();
+        let started = Instant::now();
+        state
+            .execute_input_action(Input::Button(1), action, &mut LazyPerformer::default(), true)
+            .await;
+
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert!(state.background_actions.lock().await.contains_key(&Input::Button(1)));
+    }
+
+    #[tokio::test]
+    async fn test_run_command() {
+        let output = run_command("echo 0; exit 1", None).await.unwrap();
+        assert_eq!(output.stdout, "0\n");
+        assert!(!output.status.success());
+
+        let slow = run_command("sleep 5", Some(Duration::from_millis(50))).await;
+        assert!(slow.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_encoder_brightness() {
+        let (state, deck, _dir) = attached_state().await;
+        deck.take_calls();
+
+        deck.send(DeviceStateUpdate::EncoderTwist(0, -2));
+
+        let (state, deck) = (&state, &deck);
+        eventually(|| async move { state.brightness.load(Ordering::Relaxed) == 90 }).await;
+        eventually(|| async move { deck.calls() == vec![DeckCall::SetBrightness(90)] }).await;
+    }
 }
@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use ajam_profile::Button;
+use tokio::time::Instant;
+
+/// Gesture is a recognized button interaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Gesture {
+    Tap,
+    LongPress,
+    DoubleTap,
+}
+
+/// GestureConfig holds the gesture timings of a single button.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct GestureConfig {
+    pub long_press: Option<Duration>,
+    pub double_tap: Option<Duration>,
+}
+
+impl From<&Button> for GestureConfig {
+    fn from(button: &Button) -> Self {
+        Self {
+            long_press: button
+                .long_press
+                .as_ref()
+                .map(|long_press| Duration::from_millis(long_press.threshold_ms)),
+            double_tap: button
+                .double_tap
+                .as_ref()
+                .map(|double_tap| Duration::from_millis(double_tap.interval_ms)),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum KeyState<T> {
+    /// The key is held, a long press fires at the deadline.
+    Pressed {
+        deadline: Option<Instant>,
+        config: GestureConfig,
+        payload: T,
+    },
+    /// The key was tapped once, a tap fires at the deadline unless it is tapped again.
+    Released { deadline: Instant, payload: T },
+    /// The gesture already fired, the key is ignored until released.
+    Consumed,
+}
+
+/// GestureTracker turns button presses and releases into gestures.
+///
+/// Each tracked key carries a payload, returned along with its gesture,
+/// so the gesture is resolved against the button that was pressed.
+#[derive(Debug)]
+pub(crate) struct GestureTracker<T> {
+    keys: HashMap<u8, KeyState<T>>,
+}
+
+impl<T> Default for GestureTracker<T> {
+    fn default() -> Self {
+        Self {
+            keys: HashMap::new(),
+        }
+    }
+}
+
+impl<T> GestureTracker<T> {
+    pub fn is_tracking(&self, key: u8) -> bool {
+        self.keys.contains_key(&key)
+    }
+
+    pub fn press(
+        &mut self,
+        key: u8,
+        now: Instant,
+        config: GestureConfig,
+        payload: T,
+    ) -> Option<(Gesture, T)> {
+        if let Some(KeyState::Released { deadline, .. }) = self.keys.get(&key) {
+            if now <= *deadline {
+                if let Some(KeyState::Released { payload, .. }) =
+                    self.keys.insert(key, KeyState::Consumed)
+                {
+                    return Some((Gesture::DoubleTap, payload));
+                }
+            }
+        }
+
+        let deadline = config.long_press.map(|threshold| now + threshold);
+        self.keys.insert(
+            key,
+            KeyState::Pressed {
+                deadline,
+                config,
+                payload,
+            },
+        );
+        None
+    }
+
+    pub fn release(&mut self, key: u8, now: Instant) -> Option<(Gesture, T)> {
+        match self.keys.remove(&key)? {
+            KeyState::Pressed {
+                config, payload, ..
+            } => match config.double_tap {
+                Some(interval) => {
+                    let deadline = now + interval;
+                    self.keys
+                        .insert(key, KeyState::Released { deadline, payload });
+                    None
+                }
+                None => Some((Gesture::Tap, payload)),
+            },
+            state @ KeyState::Released { .. } => {
+                self.keys.insert(key, state);
+                None
+            }
+            KeyState::Consumed => None,
+        }
+    }
+
+    /// Returns gestures whose deadlines have passed.
+    pub fn poll(&mut self, now: Instant) -> Vec<(u8, Gesture, T)> {
+        let expired: Vec<u8> = self
+            .keys
+            .iter()
+            .filter_map(|(key, state)| match state {
+                KeyState::Pressed {
+                    deadline: Some(deadline),
+                    ..
+                }
+                | KeyState::Released { deadline, .. }
+                    if *deadline <= now =>
+                {
+                    Some(*key)
+                }
+                _ => None,
+            })
+            .collect();
+
+        let mut gestures = Vec::new();
+        for key in expired {
+            match self.keys.remove(&key) {
+                Some(KeyState::Pressed { payload, .. }) => {
+                    self.keys.insert(key, KeyState::Consumed);
+                    gestures.push((key, Gesture::LongPress, payload));
+                }
+                Some(KeyState::Released { payload, .. }) => {
+                    gestures.push((key, Gesture::Tap, payload));
+                }
+                _ => {}
+            }
+        }
+        gestures
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.keys
+            .values()
+            .filter_map(|state| match state {
+                KeyState::Pressed { deadline, .. } => *deadline,
+                KeyState::Released { deadline, .. } => Some(*deadline),
+                KeyState::Consumed => None,
+            })
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LONG_PRESS: Duration = Duration::from_millis(500);
+    const DOUBLE_TAP: Duration = Duration::from_millis(300);
+
+    fn config(long_press: bool, double_tap: bool) -> GestureConfig {
+        GestureConfig {
+            long_press: long_press.then_some(LONG_PRESS),
+            double_tap: double_tap.then_some(DOUBLE_TAP),
+        }
+    }
+
+    #[test]
+    fn test_tap_without_double_tap() {
+        let mut tracker = GestureTracker::default();
+        let now = Instant::now();
+
+        assert_eq!(tracker.press(0, now, config(true, false), ()), None);
+        assert_eq!(
+            tracker.release(0, now + Duration::from_millis(100)),
+            Some((Gesture::Tap, ()))
+        );
+        assert!(!tracker.is_tracking(0));
+    }
+
+    #[test]
+    fn test_long_press() {
+        let mut tracker = GestureTracker::default();
+        let now = Instant::now();
+
+        tracker.press(0, now, config(true, true), ());
+        assert_eq!(tracker.next_deadline(), Some(now + LONG_PRESS));
+        assert!(tracker.poll(now + Duration::from_millis(100)).is_empty());
+        assert_eq!(
+            tracker.poll(now + LONG_PRESS),
+            vec![(0, Gesture::LongPress, ())]
+        );
+        assert_eq!(tracker.release(0, now + LONG_PRESS * 2), None);
+        assert!(!tracker.is_tracking(0));
+    }
+
+    #[test]
+    fn test_double_tap() {
+        let mut tracker = GestureTracker::default();
+        let now = Instant::now();
+        let step = Duration::from_millis(50);
+
+        tracker.press(0, now, config(false, true), 1);
+        assert_eq!(tracker.release(0, now + step), None);
+        assert_eq!(
+            tracker.press(0, now + step * 2, config(false, true), 2),
+            Some((Gesture::DoubleTap, 1))
+        );
+        assert_eq!(tracker.release(0, now + step * 3), None);
+        assert!(tracker.poll(now + DOUBLE_TAP * 2).is_empty());
+    }
+
+    #[test]
+    fn test_single_tap_waits_for_interval() {
+        let mut tracker = GestureTracker::default();
+        let now = Instant::now();
+        let step = Duration::from_millis(50);
+
+        tracker.press(0, now, config(false, true), ());
+        tracker.release(0, now + step);
+        assert_eq!(tracker.next_deadline(), Some(now + step + DOUBLE_TAP));
+        assert!(tracker.poll(now + step * 2).is_empty());
+        assert_eq!(
+            tracker.poll(now + step + DOUBLE_TAP),
+            vec![(0, Gesture::Tap, ())]
+        );
+        assert_eq!(tracker.next_deadline(), None);
+    }
+}
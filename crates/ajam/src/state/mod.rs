@@ -1,72 +1,228 @@
 mod activity;
+mod animate;
+mod conditions;
 mod connect;
+mod control;
+mod decks;
+mod dynamic_image;
 mod events;
+mod gestures;
 mod navigation;
+mod persist;
+mod reload;
 mod render;
+mod sequence;
+mod toggle;
+#[cfg(test)]
+pub(crate) mod testing;
 
-use ajazz_sdk::AsyncAjazz;
+use animate::Animator;
+use navigation::{Location, NavigationState};
+use persist::PersistedState;
 use render::MaterializedPage;
+use sequence::Input;
+use std::path::PathBuf;
 use std::sync::atomic::AtomicU8;
 use std::sync::Arc;
-use std::{collections::HashMap, num::NonZero};
-use tokio::sync::{Mutex, RwLock};
+use std::{collections::{HashMap, HashSet}, num::NonZero};
+use tokio::sync::{Mutex, Notify, RwLock};
+use tokio::task::JoinHandle;
 
-use ajam_profile::{ImageCache, Page, Profile};
+use ajam_profile::{ImageCache, Page, Profile, ProfileMatcher};
+use ajazz_sdk::info::Kind;
 
-pub(crate) use activity::ActivityHandler;
-pub(crate) use connect::StateConnect;
+use crate::config::Config;
+use crate::deck::SharedDeck;
+use crate::print_warning;
+use colored::Colorize;
 
-pub const DEFAULT_PROFILE: &str = "common";
-pub const DEFAULT_PAGE: &str = "main";
+pub(crate) use activity::ActivityHandler;
+pub(crate) use decks::Decks;
+pub(crate) use navigation::NavigationError;
+pub(crate) use reload::ReloadError;
+pub(crate) use render::RenderError;
 
-#[derive(Debug, Clone)]
-struct NavigationState {
-    pub profile: String,
-    pub page: String,
-}
+/// DeckId is the kind and serial number of a deck, profiles are shown on the decks they target.
+type DeckId = (Kind, String);
 
 #[derive(Clone)]
 pub(crate) struct State {
-    dev: Arc<RwLock<Option<AsyncAjazz>>>,
+    config: Arc<Config>,
+    dev: Arc<RwLock<Option<SharedDeck>>>,
+    /// DeviceTask is the task handling the input of the attached deck.
+    device_task: Arc<Mutex<Option<JoinHandle<()>>>>,
+    /// ConnectionLost is notified when a deck stops responding, it is shared by the decks.
+    connection_lost: Arc<Notify>,
     brightness: Arc<AtomicU8>,
 
+    profiles_dir: Arc<PathBuf>,
     profiles: Arc<RwLock<HashMap<String, Profile>>>,
-    active_profile: Arc<RwLock<String>>,
+    /// Matcher picks profiles for the focused app, it is rebuilt when the profiles are loaded.
+    matcher: Arc<RwLock<ProfileMatcher>>,
+    /// MatchedProfile is the profile last matched for the focused app, the deck
+    /// only follows the focus again when the match changes.
+    matched_profile: Arc<Mutex<Option<String>>>,
     navigation: Arc<RwLock<NavigationState>>,
     image_cache: Arc<Mutex<ImageCache>>,
     page_cache: Arc<Mutex<MaterializedPage>>,
+    animator: Arc<Mutex<Animator>>,
+    animation_changed: Arc<Notify>,
 
     audio_output_device: Arc<RwLock<String>>,
     audio_input_device: Arc<RwLock<String>>,
+    displays: Arc<RwLock<Vec<String>>>,
+    /// ConditionCommands are the results of the commands conditions depend on, from their last run.
+    condition_commands: Arc<RwLock<HashMap<String, bool>>>,
+    /// ConditionalPages are the pages of the shown profile whose condition held on the last check.
+    conditional_pages: Arc<Mutex<HashSet<Location>>>,
+
+    /// BackgroundActions are the sequences and slow typing running in the background,
+    /// by the input they were started from.
+    background_actions: Arc<Mutex<HashMap<Input, JoinHandle<()>>>>,
+    toggles: Arc<RwLock<HashMap<String, bool>>>,
+    command_outputs: Arc<RwLock<HashMap<String, String>>>,
+
+    state_file: Option<Arc<PathBuf>>,
+    persisted: Arc<Mutex<PersistedState>>,
+    /// SaveRequested is notified when the persisted state changed, the file is written shortly after.
+    save_requested: Arc<Notify>,
 }
 
 impl State {
-    pub fn with_profiles(profiles: HashMap<String, Profile>) -> Self {
+    pub fn with_profiles(profiles_dir: PathBuf, profiles: HashMap<String, Profile>) -> Self {
+        let config = Config::default();
         Self {
+            navigation: Arc::new(RwLock::new(new_navigation(&config, HashMap::new()))),
+            brightness: Arc::new(AtomicU8::new(config.brightness.initial)),
+            image_cache: Arc::new(Mutex::new(new_image_cache(&config))),
+            config: Arc::new(config),
             dev: Arc::new(RwLock::new(None)),
+            device_task: Arc::new(Mutex::new(None)),
+            connection_lost: Arc::new(Notify::new()),
+            profiles_dir: Arc::new(profiles_dir),
+            matcher: Arc::new(RwLock::new(ProfileMatcher::new(profiles.values()))),
+            matched_profile: Arc::new(Mutex::new(None)),
             profiles: Arc::new(RwLock::new(profiles)),
-            active_profile: Arc::new(RwLock::new(DEFAULT_PROFILE.to_string())),
-            navigation: Arc::new(RwLock::new(NavigationState {
-                profile: DEFAULT_PROFILE.to_string(),
-                page: DEFAULT_PAGE.to_string(),
-            })),
-            brightness: Arc::new(AtomicU8::new(100)),
-            image_cache: Arc::new(Mutex::new(ImageCache::new(NonZero::new(120).unwrap()))),
             page_cache: Arc::new(Mutex::new(MaterializedPage::default())),
+            animator: Arc::new(Mutex::new(Animator::default())),
+            animation_changed: Arc::new(Notify::new()),
             audio_output_device: Arc::new(RwLock::new(String::new())),
             audio_input_device: Arc::new(RwLock::new(String::new())),
+            displays: Arc::new(RwLock::new(Vec::new())),
+            condition_commands: Arc::new(RwLock::new(HashMap::new())),
+            conditional_pages: Arc::new(Mutex::new(HashSet::new())),
+            background_actions: Arc::new(Mutex::new(HashMap::new())),
+            toggles: Arc::new(RwLock::new(HashMap::new())),
+            command_outputs: Arc::new(RwLock::new(HashMap::new())),
+            state_file: None,
+            persisted: Arc::new(Mutex::new(PersistedState::default())),
+            save_requested: Arc::new(Notify::new()),
         }
     }
 
+    /// Uses the brightness, image cache size and defaults of the config.
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.navigation = Arc::new(RwLock::new(new_navigation(&config, HashMap::new())));
+        self.brightness = Arc::new(AtomicU8::new(config.brightness.initial));
+        self.image_cache = Arc::new(Mutex::new(new_image_cache(&config)));
+        self.config = Arc::new(config);
+        self
+    }
+
+    /// Keeps persisted parts of the state in the file, restoring them from it.
+    pub fn with_state_file(mut self, path: PathBuf) -> Self {
+        let persisted = match PersistedState::load(&path) {
+            Ok(persisted) => persisted,
+            Err(e) => {
+                print_warning!("Ignoring state file {}: {}", path.display(), e);
+                PersistedState::default()
+            }
+        };
+        self.toggles = Arc::new(RwLock::new(persisted.toggles.clone()));
+        let navigation = new_navigation(&self.config, persisted.last_pages.clone());
+        self.navigation = Arc::new(RwLock::new(navigation));
+        self.persisted = Arc::new(Mutex::new(persisted));
+        self.state_file = Some(Arc::new(path));
+        self
+    }
+
+    /// Creates the state of another deck. Profiles, images, toggles and the
+    /// system state are shared, the navigation and rendering are its own.
+    ///
+    /// The deck gets back the brightness and page it had when the daemon stopped.
+    pub async fn for_deck(&self, serial: &str) -> Self {
+        let (last_pages, persisted) = {
+            let persisted = self.persisted.lock().await;
+            (persisted.last_pages.clone(), persisted.decks.get(serial).cloned())
+        };
+        let mut navigation = new_navigation(&self.config, last_pages);
+        let mut brightness = self.config.brightness.initial;
+        if let Some(deck) = persisted {
+            navigation.profile = deck.profile;
+            navigation.page = deck.page;
+            brightness = deck.brightness.min(100);
+        }
+        Self {
+            dev: Arc::new(RwLock::new(None)),
+            device_task: Arc::new(Mutex::new(None)),
+            brightness: Arc::new(AtomicU8::new(brightness)),
+            navigation: Arc::new(RwLock::new(navigation)),
+            matched_profile: Arc::new(Mutex::new(None)),
+            page_cache: Arc::new(Mutex::new(MaterializedPage::default())),
+            animator: Arc::new(Mutex::new(Animator::default())),
+            animation_changed: Arc::new(Notify::new()),
+            conditional_pages: Arc::new(Mutex::new(HashSet::new())),
+            background_actions: Arc::new(Mutex::new(HashMap::new())),
+            ..self.clone()
+        }
+    }
+
+    /// Replaces the profiles, rebuilding the matcher for them.
+    async fn set_profiles(&self, profiles: HashMap<String, Profile>) {
+        *self.matcher.write().await = ProfileMatcher::new(profiles.values());
+        *self.profiles.write().await = profiles;
+    }
+
+    async fn deck_id(&self) -> Option<DeckId> {
+        let dev = self.dev.read().await;
+        dev.as_ref().map(|dev| (dev.kind(), dev.serial()))
+    }
+
     async fn get_page(&self, profile: &str, page: &str) -> Option<(Profile, Page)> {
+        let deck = self.deck_id().await;
         let profiles_guard = self.profiles.read().await;
 
-        let profile = profiles_guard.get(profile)?;
+        let profile = profiles_guard.get(profile).filter(|p| is_for_deck(p, deck.as_ref()))?;
         let page = profile.manifest.get_page(page)?;
 
         Some((profile.clone(), page.clone()))
     }
 
+    /// Returns the profile shown when no other one applies. It is the common profile,
+    /// or the first `common-*` one made for the deck when the common one is not.
+    async fn default_profile(&self) -> String {
+        let default = &self.config.defaults.profile;
+        let deck = self.deck_id().await;
+        let profiles = self.profiles.read().await;
+        let common = profiles.get(default);
+        if common.map_or(true, |common| is_for_deck(common, deck.as_ref())) {
+            return default.clone();
+        }
+        // Profiles named with the prefix replace the common one on decks it is not made for.
+        let prefix = format!("{}-", default);
+        profiles
+            .values()
+            .filter(|p| p.name.starts_with(&prefix) && is_for_deck(p, deck.as_ref()))
+            .map(|p| p.name.clone())
+            .min()
+            .unwrap_or_else(|| default.clone())
+    }
+
+    /// Returns the page profiles are opened on.
+    fn default_page(&self) -> &str {
+        &self.config.defaults.page
+    }
+
     async fn set_audio_output_device(&self, device: &str) {
         *self.audio_output_device.write().await = device.to_string();
     }
@@ -74,4 +230,26 @@ impl State {
     async fn set_audio_input_device(&self, device: &str) {
         *self.audio_input_device.write().await = device.to_string();
     }
+
+    async fn set_displays(&self, displays: Vec<String>) {
+        *self.displays.write().await = displays;
+    }
+}
+
+fn new_navigation(config: &Config, last_pages: HashMap<String, String>) -> NavigationState {
+    let location = Location::new(&config.defaults.profile, &config.defaults.page);
+    NavigationState::new(location, last_pages)
+}
+
+fn new_image_cache(config: &Config) -> ImageCache {
+    // The size is validated when the config is loaded.
+    ImageCache::new(NonZero::new(config.image_cache_size).unwrap_or(NonZero::<usize>::MIN))
+}
+
+/// Returns whether the profile is shown on the deck, every profile is until one is attached.
+fn is_for_deck(profile: &Profile, deck: Option<&DeckId>) -> bool {
+    match deck {
+        Some((kind, serial)) => profile.manifest.targets(*kind, serial),
+        None => true,
+    }
 }
@@ -1,9 +1,14 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use ajam_profile::{NavigationCommand, Profile};
 use thiserror::Error;
 
 use crate::print_debug;
 use colored::Colorize;
 
 use super::{
+    is_for_deck,
     render::{RenderError, StateRender},
     State,
 };
@@ -20,8 +25,95 @@ pub enum NavigationError {
     RenderError(#[from] RenderError),
 }
 
-pub const DEFAULT_PROFILE: &str = "common";
-const DEFAULT_PAGE: &str = "main";
+/// How deep pages can be nested before the oldest ones are forgotten.
+const MAX_STACK_DEPTH: usize = 32;
+
+/// Location is a page of a profile.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct Location {
+    pub profile: String,
+    pub page: String,
+}
+
+impl Location {
+    pub fn new(profile: &str, page: &str) -> Self {
+        Self {
+            profile: profile.to_string(),
+            page: page.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}::{}", self.profile, self.page)
+    }
+}
+
+/// Transition is how a navigation changes the stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Transition {
+    /// Push keeps the current page to return to it with `back`.
+    Push,
+    /// Replace swaps the current page, e.g. when paging through siblings.
+    Replace,
+    /// Reset forgets the stack, e.g. when another app is focused.
+    Reset,
+}
+
+/// NavigationState is the shown page and the pages to return to.
+#[derive(Debug, Clone)]
+pub(crate) struct NavigationState {
+    pub profile: String,
+    pub page: String,
+    /// Stack holds the pages `back` returns to, the last one first.
+    pub stack: Vec<Location>,
+    /// LastPages is the page last shown in each profile.
+    pub last_pages: HashMap<String, String>,
+}
+
+impl NavigationState {
+    /// Creates the navigation starting on the default location.
+    pub fn new(location: Location, last_pages: HashMap<String, String>) -> Self {
+        Self {
+            profile: location.profile,
+            page: location.page,
+            stack: Vec::new(),
+            last_pages,
+        }
+    }
+
+    pub fn location(&self) -> Location {
+        Location::new(&self.profile, &self.page)
+    }
+
+    fn apply(&mut self, location: Location, transition: Transition) {
+        match transition {
+            Transition::Push if location != self.location() => {
+                self.stack.push(self.location());
+                if self.stack.len() > MAX_STACK_DEPTH {
+                    self.stack.remove(0);
+                }
+            }
+            Transition::Push | Transition::Replace => {}
+            Transition::Reset => self.stack.clear(),
+        }
+        self.last_pages
+            .insert(location.profile.clone(), location.page.clone());
+        self.profile = location.profile;
+        self.page = location.page;
+    }
+
+    /// Describes the stack from the bottom to the shown page.
+    fn describe(&self) -> String {
+        self.stack
+            .iter()
+            .chain(std::iter::once(&self.location()))
+            .map(Location::to_string)
+            .collect::<Vec<_>>()
+            .join(" > ")
+    }
+}
 
 pub trait Navigator {
     async fn navigate_to(&self, profile: &str, page: &str) -> Result<(), NavigationError>;
@@ -33,6 +125,9 @@ pub trait Navigator {
 
     async fn navigate_to_next_page(&self) -> Result<(), NavigationError>;
     async fn navigate_to_previous_page(&self) -> Result<(), NavigationError>;
+    async fn navigate_back(&self) -> Result<(), NavigationError>;
+
+    async fn run_navigation_command(&self, command: NavigationCommand) -> Result<(), NavigationError>;
 }
 
 impl Navigator for State {
@@ -41,64 +136,44 @@ impl Navigator for State {
         profile_name: &str,
         page_name: &str,
     ) -> Result<(), NavigationError> {
-        print_debug!("Navigating to {}::{}", profile_name, page_name);
-        let profile = {
-            let profiles_guard = self.profiles.read().await;
-            if let Some(profile) = profiles_guard.get(profile_name) {
-                profile.clone()
-            } else {
-                return Err(NavigationError::NoProfile);
-            }
-        };
-
-        let Some(page) = profile.manifest.get_page(page_name) else {
-            return Err(NavigationError::NoPage);
-        };
-
-        {
-            let mut navigation_guard = self.navigation.write().await;
-            navigation_guard.profile = profile_name.to_string();
-            navigation_guard.page = page_name.to_string();
-        }
-
-        self.render_page(&profile, page).await?;
-        Ok(())
+        self.show_location(Location::new(profile_name, page_name), Transition::Push)
+            .await
     }
 
     async fn navigate_to_default(&self) -> Result<(), NavigationError> {
-        self.navigate_to(DEFAULT_PROFILE, DEFAULT_PAGE).await
+        let default = self.default_profile().await;
+        self.navigate_to(&default, self.default_page()).await
     }
 
+    /// Leaves an app profile for the common one, or returns to the app it was left from.
     async fn toggle_home(&self) -> Result<(), NavigationError> {
-        let profile_name = {
-            let navigation_guard = self.navigation.read().await;
-            navigation_guard.profile.clone()
-        };
-
-        let active_profile = {
-            let active_profile_guard = self.active_profile.read().await;
-            active_profile_guard.clone()
+        let default = self.default_profile().await;
+        let (profile_name, app) = {
+            let navigation = self.navigation.read().await;
+            let app = navigation
+                .stack
+                .iter()
+                .rposition(|location| location.profile != default);
+            (navigation.profile.clone(), app)
         };
 
-        if active_profile == DEFAULT_PROFILE && profile_name == DEFAULT_PROFILE {
-            print_debug!("Already on default profile and no saved profile, skipping");
-            return Ok(());
-        }
-
-        if profile_name != DEFAULT_PROFILE {
-            let mut active_profile_guard = self.active_profile.write().await;
-            *active_profile_guard = profile_name.clone();
+        if profile_name != default {
             return self.navigate_to_default().await;
         }
 
-        if profile_name == DEFAULT_PROFILE && active_profile != DEFAULT_PROFILE {
-            let mut active_profile_guard = self.active_profile.write().await;
-            let profile_to_restore = active_profile.clone();
-            *active_profile_guard = DEFAULT_PROFILE.to_string();
-            return self.navigate_to_profile_or_default(&profile_to_restore).await;
+        let Some(index) = app else {
+            print_debug!("Already on default profile and no saved profile, skipping");
+            return Ok(());
+        };
+        let location = {
+            let mut navigation = self.navigation.write().await;
+            navigation.stack.truncate(index + 1);
+            navigation.stack.pop()
+        };
+        match location {
+            Some(location) => self.show_location(location, Transition::Replace).await,
+            None => Ok(()),
         }
-
-        Ok(())
     }
 
     async fn navigate_to_page(&self, page: &str) -> Result<(), NavigationError> {
@@ -110,23 +185,38 @@ impl Navigator for State {
         self.navigate_to(&profile, page).await
     }
 
+    /// Shows an app profile on the page last used in it, the stack starts over.
     async fn navigate_to_profile_or_default(&self, profile: &str) -> Result<(), NavigationError> {
-        let profile_name = {
+        let remember = match self.profiles.read().await.get(profile) {
+            Some(profile) => profile.manifest.last_page.remember,
+            None => false,
+        };
+        let (profile_name, last_page) = {
             let navigation_guard = self.navigation.read().await;
-            navigation_guard.profile.clone()
+            let last_page = navigation_guard.last_pages.get(profile).filter(|_| remember);
+            (navigation_guard.profile.clone(), last_page.cloned())
         };
         if profile_name == profile {
             print_debug!("Already on profile {}, skipping", profile);
             return Ok(());
         }
-        match self.navigate_to(profile, DEFAULT_PAGE).await {
+
+        let page = match last_page {
+            Some(page) => self.get_page(profile, &page).await.map(|_| page),
+            None => None,
+        };
+        let page = page.as_deref().unwrap_or(self.default_page());
+        match self.show_location(Location::new(profile, page), Transition::Reset).await {
             Ok(_) => Ok(()),
             Err(NavigationError::NoProfile) => {
-                if profile_name == DEFAULT_PROFILE {
+                let default = self.default_profile().await;
+                if profile_name == default {
                     print_debug!("Already on default profile, skipping");
+                    self.navigation.write().await.stack.clear();
                     return Ok(());
                 }
-                self.navigate_to_default().await
+                let location = Location::new(&default, self.default_page());
+                self.show_location(location, Transition::Reset).await
             },
             Err(e) => Err(e),
         }
@@ -135,32 +225,255 @@ impl Navigator for State {
     async fn navigate_to_next_page(&self) -> Result<(), NavigationError> {
         self.navigate_page_offset(1).await
     }
-    
+
     async fn navigate_to_previous_page(&self) -> Result<(), NavigationError> {
         self.navigate_page_offset(-1).await
     }
+
+    /// Returns to the page the current one was opened from.
+    ///
+    /// Pages removed since then are skipped.
+    async fn navigate_back(&self) -> Result<(), NavigationError> {
+        loop {
+            let Some(location) = self.navigation.write().await.stack.pop() else {
+                print_debug!("Navigation stack is empty, skipping");
+                return Ok(());
+            };
+            match self.show_location(location, Transition::Replace).await {
+                Err(NavigationError::NoProfile) | Err(NavigationError::NoPage) => continue,
+                result => return result,
+            }
+        }
+    }
+
+    async fn run_navigation_command(&self, command: NavigationCommand) -> Result<(), NavigationError> {
+        match command {
+            NavigationCommand::Next => self.navigate_to_next_page().await,
+            NavigationCommand::Previous => self.navigate_to_previous_page().await,
+            NavigationCommand::Home => self.toggle_home().await,
+            NavigationCommand::Back => self.navigate_back().await,
+        }
+    }
 }
 
 impl State {
+    /// Shows a page, changing the navigation stack according to the transition.
+    pub(super) async fn show_location(
+        &self,
+        location: Location,
+        transition: Transition,
+    ) -> Result<(), NavigationError> {
+        print_debug!("Navigating to {}", location);
+        let profile = self.get_profile(&location.profile).await?;
+        let Some(page) = profile.manifest.get_page(&location.page) else {
+            return Err(NavigationError::NoPage);
+        };
+
+        {
+            let mut navigation_guard = self.navigation.write().await;
+            navigation_guard.apply(location.clone(), transition);
+            print_debug!("Navigation stack: {}", navigation_guard.describe());
+        }
+        self.persist_last_page(&profile, &location).await;
+        self.persist_deck().await;
+
+        self.render_page(&profile, page).await?;
+        Ok(())
+    }
+
+    async fn persist_last_page(&self, profile: &Profile, location: &Location) {
+        let last_page = profile.manifest.last_page;
+        if !last_page.remember || !last_page.persist {
+            return;
+        }
+        if self.state_file.is_none() {
+            return;
+        }
+
+        let mut persisted = self.persisted.lock().await;
+        if persisted.last_pages.get(&location.profile) == Some(&location.page) {
+            return;
+        }
+        persisted
+            .last_pages
+            .insert(location.profile.clone(), location.page.clone());
+        self.request_save();
+    }
+
     async fn get_current_profile_and_page(&self) -> (String, String) {
         let navigation = self.navigation.read().await;
         (navigation.profile.clone(), navigation.page.clone())
     }
 
-    async fn get_profile(&self, profile_name: &str) -> Result<crate::state::Profile, NavigationError> {
+    /// Returns the profile if it is made for the attached deck.
+    pub(super) async fn get_profile(&self, profile_name: &str) -> Result<Profile, NavigationError> {
+        let deck = self.deck_id().await;
         let profiles_guard = self.profiles.read().await;
-        profiles_guard.get(profile_name).cloned().ok_or(NavigationError::NoProfile)
+        profiles_guard
+            .get(profile_name)
+            .filter(|profile| is_for_deck(profile, deck.as_ref()))
+            .cloned()
+            .ok_or(NavigationError::NoProfile)
     }
 
     async fn navigate_page_offset(&self, offset: isize) -> Result<(), NavigationError> {
         let (profile_name, page_name) = self.get_current_profile_and_page().await;
         let profile = self.get_profile(&profile_name).await?;
 
-        let len = profile.manifest.pages.len() as isize;
-        let current_index = profile.manifest.page_index(&page_name).unwrap() as isize;
+        let len = profile.manifest.pages_order.len() as isize;
+        let Some(current_index) = profile.manifest.page_index(&page_name) else {
+            return Err(NavigationError::NoPage);
+        };
+        let current_index = current_index as isize;
         let new_index = (current_index + offset + len) % len;
 
-        let new_page_name = profile.manifest.pages_order[new_index as usize].clone();
-        self.navigate_to(&profile_name, &new_page_name).await
+        let new_page_name = &profile.manifest.pages_order[new_index as usize];
+        self.show_location(Location::new(&profile_name, new_page_name), Transition::Replace)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ajam_profile::open_profiles;
+    use ajazz_sdk::info::Kind;
+
+    use crate::config::Config;
+    use crate::deck::{DeckCall, VirtualDeck};
+    use crate::state::persist::PersistedState;
+    use crate::state::testing::{
+        attached_state, attached_state_with, write_profile, TempDir, TEST_MANIFEST,
+    };
+    use crate::state::connect::StateConnect;
+
+    use super::*;
+
+    async fn stack(state: &State) -> Vec<String> {
+        let navigation = state.navigation.read().await;
+        navigation.stack.iter().map(Location::to_string).collect()
+    }
+
+    #[tokio::test]
+    async fn test_nested_pages() {
+        let manifest = TEST_MANIFEST
+            .replace("  - second\n", "  - second\n  - third\n")
+            .replace("encoders:", "  third: {}\nencoders:");
+        let (state, _deck, _dir) = attached_state_with(&manifest).await;
+
+        state.navigate_to_page("second").await.unwrap();
+        state.navigate_to_page("third").await.unwrap();
+        assert_eq!(stack(&state).await, vec!["common::main", "common::second"]);
+
+        state.navigate_back().await.unwrap();
+        assert_eq!(state.navigation.read().await.page, "second");
+        state.navigate_back().await.unwrap();
+        assert_eq!(state.navigation.read().await.page, "main");
+        assert!(stack(&state).await.is_empty());
+
+        // Nothing to return to.
+        state.navigate_back().await.unwrap();
+        assert_eq!(state.navigation.read().await.page, "main");
+    }
+
+    #[tokio::test]
+    async fn test_paging_does_not_push() {
+        let (state, _deck, _dir) = attached_state().await;
+
+        state.navigate_to_next_page().await.unwrap();
+        assert_eq!(state.navigation.read().await.page, "second");
+        assert!(stack(&state).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_app_restores_last_page() {
+        let (state, _deck, dir) = attached_state().await;
+        write_profile(dir.path(), "app", TEST_MANIFEST, &["a.bmp", "b.bmp"]);
+        let profiles = ajam_profile::open_profiles(dir.path()).unwrap();
+        state.set_profiles(profiles).await;
+
+        state.navigate_to_profile_or_default("app").await.unwrap();
+        state.navigate_to_page("second").await.unwrap();
+
+        // Home leaves the app and returns to the page it was left from.
+        state.toggle_home().await.unwrap();
+        assert_eq!(state.navigation.read().await.location(), Location::new("common", "main"));
+        state.toggle_home().await.unwrap();
+        assert_eq!(state.navigation.read().await.location(), Location::new("app", "second"));
+
+        state.navigate_to_profile_or_default("missing").await.unwrap();
+        assert_eq!(state.navigation.read().await.profile, "common");
+        assert!(stack(&state).await.is_empty());
+
+        state.navigate_to_profile_or_default("app").await.unwrap();
+        assert_eq!(state.navigation.read().await.location(), Location::new("app", "second"));
+    }
+
+    #[tokio::test]
+    async fn test_app_opts_out_of_last_page() {
+        let (state, _deck, dir) = attached_state().await;
+        let manifest = format!("last_page:\n  remember: false\n{}", TEST_MANIFEST);
+        write_profile(dir.path(), "app", &manifest, &["a.bmp", "b.bmp"]);
+        state.set_profiles(open_profiles(dir.path()).unwrap()).await;
+
+        state.navigate_to_profile_or_default("app").await.unwrap();
+        state.navigate_to_page("second").await.unwrap();
+        state.navigate_to_profile_or_default("common").await.unwrap();
+
+        state.navigate_to_profile_or_default("app").await.unwrap();
+        assert_eq!(state.navigation.read().await.location(), Location::new("app", "main"));
+    }
+
+    #[tokio::test]
+    async fn test_last_page_survives_restart() {
+        let dir = TempDir::new();
+        write_profile(dir.path(), "common", TEST_MANIFEST, &["a.bmp", "b.bmp"]);
+        let manifest = format!("last_page:\n  persist: true\n{}", TEST_MANIFEST);
+        write_profile(dir.path(), "app", &manifest, &["a.bmp", "b.bmp"]);
+        let state_file = dir.path().join("state.json");
+
+        let start = || async {
+            let profiles = open_profiles(dir.path()).unwrap();
+            let state = State::with_profiles(dir.path().to_path_buf(), profiles)
+                .with_state_file(state_file.clone());
+            state.attach_deck(Arc::new(VirtualDeck::new(Kind::Akp03))).await;
+            state
+        };
+
+        let state = start().await;
+        state.navigate_to_profile_or_default("app").await.unwrap();
+        state.navigate_to_page("second").await.unwrap();
+        state.save_state().await;
+        let persisted = PersistedState::load(&state_file).unwrap();
+        assert_eq!(persisted.last_pages.get("app").map(String::as_str), Some("second"));
+        drop(state);
+
+        let state = start().await;
+        state.navigate_to_profile_or_default("app").await.unwrap();
+        assert_eq!(state.navigation.read().await.location(), Location::new("app", "second"));
+    }
+
+    #[tokio::test]
+    async fn test_configured_defaults() {
+        let dir = TempDir::new();
+        write_profile(dir.path(), "home", TEST_MANIFEST, &["a.bmp", "b.bmp"]);
+        write_profile(dir.path(), "app", TEST_MANIFEST, &["a.bmp", "b.bmp"]);
+        let mut config = Config::default();
+        config.defaults.profile = "home".to_string();
+        config.defaults.page = "second".to_string();
+        config.brightness.initial = 40;
+
+        let profiles = open_profiles(dir.path()).unwrap();
+        let state = State::with_profiles(dir.path().to_path_buf(), profiles).with_config(config);
+        let deck = Arc::new(VirtualDeck::new(Kind::Akp03));
+        state.attach_deck(deck.clone()).await;
+        assert_eq!(state.navigation.read().await.location(), Location::new("home", "second"));
+        assert!(deck.calls().contains(&DeckCall::SetBrightness(40)));
+
+        state.navigate_to_profile_or_default("app").await.unwrap();
+        assert_eq!(state.navigation.read().await.location(), Location::new("app", "second"));
+        state.toggle_home().await.unwrap();
+        assert_eq!(state.navigation.read().await.profile, "home");
     }
 }
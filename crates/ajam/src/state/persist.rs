@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::time::sleep;
+
+use crate::print_error;
+
+use super::{Decks, State};
+
+/// How long changes must settle before the state file is written, e.g. while an encoder turns.
+const SAVE_DELAY: Duration = Duration::from_millis(500);
+
+#[derive(Error, Debug)]
+pub enum PersistError {
+    #[error("failed to access state file: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("invalid state file: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// PersistedState is the part of the daemon state kept across restarts.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub(crate) struct PersistedState {
+    /// Toggles is a map of toggle keys to their states.
+    #[serde(default)]
+    pub toggles: HashMap<String, bool>,
+    /// LastPages is a map of profile names to the page last shown in them.
+    #[serde(default)]
+    pub last_pages: HashMap<String, String>,
+    /// Decks is a map of deck serial numbers to their brightness and shown page.
+    #[serde(default)]
+    pub decks: HashMap<String, PersistedDeck>,
+}
+
+/// PersistedDeck is the state a deck is restored to when it is attached.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct PersistedDeck {
+    pub brightness: u8,
+    pub profile: String,
+    pub page: String,
+}
+
+impl PersistedState {
+    /// Reads the state file, missing file gives the default state.
+    pub fn load(path: &Path) -> Result<Self, PersistError> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let data = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), PersistError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, serde_json::to_string_pretty(self)?)?;
+        fs::rename(temp_path, path)?;
+        Ok(())
+    }
+}
+
+impl State {
+    /// Records the brightness and the shown page of the attached deck.
+    pub(super) async fn persist_deck(&self) {
+        if self.state_file.is_none() {
+            return;
+        }
+        let Some((_, serial)) = self.deck_id().await else {
+            return;
+        };
+        let deck = {
+            let navigation = self.navigation.read().await;
+            PersistedDeck {
+                brightness: self.brightness.load(Ordering::Relaxed),
+                profile: navigation.profile.clone(),
+                page: navigation.page.clone(),
+            }
+        };
+
+        let mut persisted = self.persisted.lock().await;
+        if persisted.decks.get(&serial) == Some(&deck) {
+            return;
+        }
+        persisted.decks.insert(serial, deck);
+        self.request_save();
+    }
+
+    /// Schedules a write of the state file.
+    pub(super) fn request_save(&self) {
+        if self.state_file.is_some() {
+            self.save_requested.notify_one();
+        }
+    }
+
+    /// Writes the state file now.
+    pub(super) async fn save_state(&self) {
+        let Some(state_file) = &self.state_file else {
+            return;
+        };
+        let persisted = self.persisted.lock().await.clone();
+        if let Err(e) = persisted.save(state_file) {
+            print_error!("Failed to save state: {}", e);
+        }
+    }
+
+    /// Writes the state file once changes stop coming for a while.
+    async fn watch_state_file(&self) {
+        if self.state_file.is_none() {
+            return;
+        }
+        loop {
+            self.save_requested.notified().await;
+            loop {
+                tokio::select! {
+                    _ = self.save_requested.notified() => continue,
+                    _ = sleep(SAVE_DELAY) => break,
+                }
+            }
+            self.save_state().await;
+        }
+    }
+}
+
+impl Decks {
+    /// Writes the state file of every deck after changes.
+    pub async fn watch_state_file(&self) {
+        self.shared().watch_state_file().await;
+    }
+
+    /// Writes pending changes to the state file, e.g. before exiting.
+    pub async fn save_state(&self) {
+        self.shared().save_state().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ajam_profile::open_profiles;
+    use ajazz_sdk::info::Kind;
+    use tokio::task;
+
+    use crate::deck::{DeckCall, VirtualDeck};
+    use crate::state::navigation::{Location, Navigator};
+    use crate::state::render::StateRender;
+    use crate::state::testing::{eventually, write_profile, TempDir, TEST_MANIFEST};
+
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let dir = TempDir::new();
+        let path = dir.path().join("state").join("state.json");
+        assert!(PersistedState::load(&path).unwrap().toggles.is_empty());
+
+        let mut state = PersistedState::default();
+        state.toggles.insert("common/main/0".to_string(), true);
+        state.save(&path).unwrap();
+
+        let loaded = PersistedState::load(&path).unwrap();
+        assert_eq!(loaded.toggles.get("common/main/0"), Some(&true));
+    }
+
+    #[tokio::test]
+    async fn test_deck_restored_after_restart() {
+        let dir = TempDir::new();
+        write_profile(dir.path(), "common", TEST_MANIFEST, &["a.bmp", "b.bmp"]);
+        let state_file = dir.path().join("state.json");
+        let start = || async {
+            let profiles = open_profiles(dir.path()).unwrap();
+            let state = State::with_profiles(dir.path().to_path_buf(), profiles)
+                .with_state_file(state_file.clone());
+            let decks = Decks::new(state);
+            let deck = Arc::new(VirtualDeck::new(Kind::Akp03));
+            decks.attach(deck.clone()).await;
+            (decks, deck)
+        };
+
+        let (decks, _deck) = start().await;
+        let state = decks.get("virtual").await.unwrap();
+        state.navigate_to_page("second").await.unwrap();
+        state.set_brightness(-30).await.unwrap();
+        decks.save_state().await;
+        drop((decks, state));
+
+        let (decks, deck) = start().await;
+        let state = decks.get("virtual").await.unwrap();
+        assert_eq!(state.navigation.read().await.location(), Location::new("common", "second"));
+        assert_eq!(state.brightness.load(Ordering::Relaxed), 70);
+        assert!(deck.calls().contains(&DeckCall::SetBrightness(70)));
+    }
+
+    #[tokio::test]
+    async fn test_state_file_written_after_changes() {
+        let dir = TempDir::new();
+        write_profile(dir.path(), "common", TEST_MANIFEST, &["a.bmp", "b.bmp"]);
+        let state_file = dir.path().join("state.json");
+        let profiles = open_profiles(dir.path()).unwrap();
+        let state = State::with_profiles(dir.path().to_path_buf(), profiles)
+            .with_state_file(state_file.clone());
+        let decks = Decks::new(state);
+        decks.attach(Arc::new(VirtualDeck::new(Kind::Akp03))).await;
+        let watcher = {
+            let decks = decks.clone();
+            task::spawn(async move { decks.watch_state_file().await })
+        };
+
+        let state = decks.get("virtual").await.unwrap();
+        for _ in 0..5 {
+            state.set_brightness(-10).await.unwrap();
+        }
+        // Nothing is written while the changes keep coming.
+        assert!(!state_file.exists());
+
+        let brightness = || async {
+            let persisted = PersistedState::load(&state_file).unwrap();
+            persisted.decks.get("virtual").map(|deck| deck.brightness) == Some(50)
+        };
+        eventually(brightness).await;
+        watcher.abort();
+    }
+}
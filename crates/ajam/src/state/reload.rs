@@ -0,0 +1,240 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use ajam_profile::{open_profiles_partial, ProfileError};
+use colored::Colorize;
+use notify::{RecursiveMode, Watcher};
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+use crate::{print_debug, print_error, print_info, print_warning};
+
+use super::navigation::{Location, NavigationError, Transition};
+use super::render::{RenderError, StateRender};
+use super::{Decks, State};
+
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(300);
+
+#[derive(Error, Debug)]
+pub enum ReloadError {
+    #[error("failed to read profiles: {0}")]
+    Profile(#[from] ProfileError),
+
+    #[error("failed to restore navigation: {0}")]
+    Navigation(#[from] NavigationError),
+}
+
+pub(crate) trait StateReload {
+    /// Re-reads the profiles directory. `changed` limits image cache invalidation
+    /// to the profiles containing those paths, an empty slice invalidates everything.
+    async fn reload_profiles(&self, changed: &[PathBuf]) -> Result<(), ReloadError>;
+}
+
+impl StateReload for State {
+    async fn reload_profiles(&self, changed: &[PathBuf]) -> Result<(), ReloadError> {
+        self.load_profiles(changed).await?;
+        self.page_cache.lock().await.invalidate();
+
+        self.restore_navigation().await?;
+        Ok(())
+    }
+}
+
+impl StateReload for Decks {
+    /// Reloads the profiles shared by the decks, then refreshes every deck.
+    async fn reload_profiles(&self, changed: &[PathBuf]) -> Result<(), ReloadError> {
+        self.shared().load_profiles(changed).await?;
+        for state in self.states().await {
+            state.page_cache.lock().await.invalidate();
+            state.restore_navigation().await?;
+        }
+        Ok(())
+    }
+}
+
+impl Decks {
+    pub async fn watch_profiles(&self) {
+        let profiles_dir = self.shared().profiles_dir.clone();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let watcher =
+            notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
+                Ok(event) if !event.kind.is_access() => {
+                    let _ = tx.send(event.paths);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    print_error!("Profiles watcher error: {}", e);
+                }
+            });
+        let mut watcher = match watcher {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                print_error!("Failed to create profiles watcher: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = watcher.watch(&profiles_dir, RecursiveMode::Recursive) {
+            print_error!("Failed to watch {}: {}", profiles_dir.display(), e);
+            return;
+        }
+
+        while let Some(mut paths) = rx.recv().await {
+            while let Ok(Some(more)) = timeout(RELOAD_DEBOUNCE, rx.recv()).await {
+                paths.extend(more);
+            }
+
+            print_info!("Profiles changed, reloading");
+            if let Err(e) = self.reload_profiles(&paths).await {
+                print_error!("Failed to reload profiles: {}", e);
+            }
+        }
+    }
+}
+
+impl State {
+    /// Re-reads the profiles directory, keeping the previous version of profiles that fail to load.
+    async fn load_profiles(&self, changed: &[PathBuf]) -> Result<(), ReloadError> {
+        let loaded = open_profiles_partial(self.profiles_dir.as_path())?;
+        let mut profiles = loaded.profiles;
+
+        {
+            let previous = self.profiles.read().await;
+            for (path, e) in loaded.errors.iter() {
+                print_error!("Failed to reload profile {}: {}", path.display(), e);
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                if let Some(profile) = previous.get(name.as_ref()) {
+                    print_warning!("Keeping previous version of profile {}", name);
+                    profiles.insert(name.to_string(), profile.clone());
+                }
+            }
+        }
+
+        {
+            let affected = self.affected_profiles(changed);
+            let mut image_cache = self.image_cache.lock().await;
+            for profile in profiles.values() {
+                let is_affected = match &affected {
+                    Some(affected) => affected.contains(&profile.name),
+                    None => true,
+                };
+                if is_affected {
+                    print_debug!("Invalidating images of profile {}", profile.name);
+                    image_cache.invalidate_dir(profile.path());
+                }
+            }
+        }
+
+        print_info!("Reloaded {} profiles", profiles.len());
+        self.set_profiles(profiles).await;
+        Ok(())
+    }
+
+    /// Returns the names of profiles containing the paths, or `None` if any path
+    /// cannot be attributed to a single profile.
+    fn affected_profiles(&self, changed: &[PathBuf]) -> Option<HashSet<String>> {
+        if changed.is_empty() {
+            return None;
+        }
+
+        let canonical_dir = self.profiles_dir.canonicalize().ok();
+        let mut affected = HashSet::new();
+        for path in changed {
+            let relative = path
+                .strip_prefix(self.profiles_dir.as_path())
+                .ok()
+                .or_else(|| {
+                    canonical_dir
+                        .as_deref()
+                        .and_then(|dir| path.strip_prefix(dir).ok())
+                });
+            let name = relative
+                .and_then(|relative| relative.components().next())
+                .map(|component| component.as_os_str().to_string_lossy().to_string())?;
+            affected.insert(name);
+        }
+        Some(affected)
+    }
+
+    /// Re-renders the current page, or falls back when it no longer exists.
+    async fn restore_navigation(&self) -> Result<(), NavigationError> {
+        let result = if self.get_active_page().await.is_some() {
+            self.render_active_page()
+                .await
+                .map_err(NavigationError::from)
+        } else {
+            let profile = self.navigation.read().await.profile.clone();
+            print_warning!("Current page no longer exists, leaving it");
+            let location = Location::new(&profile, self.default_page());
+            match self.show_location(location, Transition::Replace).await {
+                Err(NavigationError::NoProfile) | Err(NavigationError::NoPage) => {
+                    let location = Location::new(&self.default_profile().await, self.default_page());
+                    self.show_location(location, Transition::Replace).await
+                }
+                result => result,
+            }
+        };
+
+        match result {
+            Err(NavigationError::RenderError(RenderError::NoDevice)) => Ok(()),
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::deck::DeckCall;
+    use crate::state::navigation::Navigator;
+    use crate::state::testing::{attached_state, write_profile, TEST_MANIFEST};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reload_rerenders_active_page() {
+        let (state, deck, dir) = attached_state().await;
+        deck.take_calls();
+
+        let manifest = TEST_MANIFEST.replace("src: a.bmp", "src: b.bmp");
+        write_profile(dir.path(), "common", &manifest, &["a.bmp", "b.bmp"]);
+        state.reload_profiles(&[]).await.unwrap();
+
+        let calls = deck.take_calls();
+        assert!(calls.contains(&DeckCall::SetButtonImage(0)));
+        assert_eq!(calls.last(), Some(&DeckCall::Flush));
+    }
+
+    #[tokio::test]
+    async fn test_reload_leaves_removed_page() {
+        let (state, _deck, dir) = attached_state().await;
+        state.navigate_to("common", "second").await.unwrap();
+
+        let start = TEST_MANIFEST.find("  second:").unwrap();
+        let end = TEST_MANIFEST.find("encoders:").unwrap();
+        let manifest = format!("{}{}", &TEST_MANIFEST[..start], &TEST_MANIFEST[end..])
+            .replace("  - second\n", "");
+        write_profile(dir.path(), "common", &manifest, &["a.bmp", "b.bmp"]);
+        state.reload_profiles(&[]).await.unwrap();
+
+        let navigation = state.navigation.read().await;
+        assert_eq!(navigation.page, "main");
+    }
+
+    #[tokio::test]
+    async fn test_reload_keeps_broken_profile() {
+        let (state, _deck, dir) = attached_state().await;
+
+        write_profile(dir.path(), "common", "pages: [", &[]);
+        let changed = vec![dir.path().join("common").join("manifest.yaml")];
+        state.reload_profiles(&changed).await.unwrap();
+
+        let profiles = state.profiles.read().await;
+        assert!(profiles
+            .get("common")
+            .unwrap()
+            .manifest
+            .pages
+            .contains_key("second"));
+    }
+}
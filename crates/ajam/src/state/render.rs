@@ -2,10 +2,11 @@ use std::sync::atomic::Ordering;
 
 use image::DynamicImage;
 use thiserror::Error;
+use tokio::time::Instant;
 
-use ajam_profile::{ButtonImage, ImageLoader, Page, Profile};
-use ajazz_sdk::AjazzError;
+use ajam_profile::{ButtonImage, ImageLoader, KeyImage, Page, Profile};
 
+use crate::deck::DeckError;
 use crate::State;
 
 #[derive(Error, Debug)]
@@ -17,7 +18,7 @@ pub enum RenderError {
     ButtonIndexOutOfBounds(usize),
 
     #[error("error writing to device")]
-    DeviceWriteError(#[from] AjazzError),
+    DeviceWriteError(#[from] DeckError),
 
     #[error("error loading image")]
     ImageError(#[from] image::ImageError),
@@ -35,6 +36,17 @@ pub enum RenderError {
 #[derive(Debug, Default, Clone)]
 pub(crate) struct MaterializedPage(Vec<Option<DynamicImage>>);
 
+impl MaterializedPage {
+    /// Forgets what is shown on the device, so the next render redraws every key.
+    pub fn invalidate(&mut self) {
+        self.0.clear();
+    }
+
+    pub fn get_mut(&mut self, key: usize) -> Option<&mut Option<DynamicImage>> {
+        self.0.get_mut(key)
+    }
+}
+
 impl State {
     async fn render_state(&self, state: &MaterializedPage) -> Result<(), RenderError> {
         // Maybe too short lock?
@@ -48,6 +60,7 @@ impl State {
 
         let mut page_cache = self.page_cache.lock().await;
 
+        let redraw = page_cache.0.is_empty();
         if page_cache.0.len() < state.0.len() {
             page_cache.0.resize(state.0.len(), None);
         }
@@ -60,7 +73,7 @@ impl State {
             }
 
             if let Some(cached_image) = page_cache.0.get(i) {
-                if cached_image == image {
+                if !redraw && cached_image == image {
                     continue;
                 }
             }
@@ -68,6 +81,7 @@ impl State {
             let Some(image) = image else {
                 dev.clear_button_image(i as u8).await?;
                 page_cache.0[i] = None;
+                changed = true;
                 continue;
             };
 
@@ -90,19 +104,25 @@ impl State {
         &self,
         profile: &Profile,
         page: &Page,
-    ) -> Result<MaterializedPage, RenderError> {
+    ) -> Result<Vec<Option<KeyImage>>, RenderError> {
         let buttons_count = profile.manifest.kind().display_key_count() as usize;
 
         let mut image_cache = self.image_cache.lock().await;
         let mut loader = profile.get_loader(&mut image_cache);
-        let mut images: Vec<Option<DynamicImage>> = vec![None; buttons_count];
+        let mut images: Vec<Option<KeyImage>> = vec![None; buttons_count];
 
         for (i, button) in page.iter_buttons(buttons_count).enumerate() {
             let Some(button) = button else {
-                return Err(RenderError::ButtonIndexOutOfBounds(i));
+                continue;
             };
 
-            let image = match &button.image {
+            let on = button.toggle().is_some() && self.is_toggled(i as u8).await;
+            let Some(image) = button.image(on) else {
+                continue;
+            };
+            loader.set_fit(button.fit.unwrap_or(profile.manifest.fit));
+            loader.set_origin(button.origin.as_deref());
+            let image = match image {
                 ButtonImage::Source { src } => loader.open(src)?,
                 ButtonImage::AudioInput { audio_input } => {
                     let input_device_name = self.audio_input_device.read().await;
@@ -112,11 +132,17 @@ impl State {
                     let output_device_name = self.audio_output_device.read().await;
                     loader.open_from_image_map(audio_output, &output_device_name)?
                 }
+                ButtonImage::Generated(spec) => loader.generate(spec)?,
+                ButtonImage::Command { command, images, .. } => {
+                    let outputs = self.command_outputs.read().await;
+                    let output = outputs.get(command).map(String::as_str).unwrap_or_default();
+                    loader.open_from_image_map(images, output)?
+                }
             };
             images[i] = Some(image)
         }
 
-        Ok(MaterializedPage(images))
+        Ok(images)
     }
 }
 
@@ -126,6 +152,7 @@ pub trait StateRender {
 
     async fn apply_brightness(&self) -> Result<(), RenderError>;
     async fn set_brightness(&self, delta: i8) -> Result<(), RenderError>;
+    async fn set_brightness_level(&self, brightness: u8) -> Result<(), RenderError>;
 
     async fn get_active_page(&self) -> Option<(Profile, Page)>;
 }
@@ -145,8 +172,10 @@ impl StateRender for State {
     }
 
     async fn render_page(&self, profile: &Profile, page: &Page) -> Result<(), RenderError> {
-        let materialized_page = self.materialize_page(profile, page).await?;
-        self.render_state(&materialized_page).await
+        let images = self.materialize_page(profile, page).await?;
+        let shown = self.animator.lock().await.show(&images, Instant::now());
+        self.animation_changed.notify_one();
+        self.render_state(&MaterializedPage(shown)).await
     }
 
     async fn render_active_page(&self) -> Result<(), RenderError> {
@@ -168,12 +197,81 @@ impl StateRender for State {
     }
 
     async fn set_brightness(&self, delta: i8) -> Result<(), RenderError> {
-        let abs_delta = delta.unsigned_abs();
-        if delta > 0 {
-            self.brightness.fetch_add(abs_delta, Ordering::Relaxed);
-        } else {
-            self.brightness.fetch_sub(abs_delta, Ordering::Relaxed);
-        }
+        let brightness = self.brightness.load(Ordering::Relaxed);
+        self.set_brightness_level(brightness.saturating_add_signed(delta)).await
+    }
+
+    async fn set_brightness_level(&self, brightness: u8) -> Result<(), RenderError> {
+        self.brightness.store(brightness.min(100), Ordering::Relaxed);
+        self.persist_deck().await;
         self.apply_brightness().await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::deck::DeckCall;
+    use crate::state::navigation::Navigator;
+    use crate::state::testing::{attached_state, write_profile};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_render_on_attach() {
+        let (_state, deck, _dir) = attached_state().await;
+
+        assert_eq!(
+            deck.calls(),
+            vec![
+                DeckCall::SetBrightness(100),
+                DeckCall::ClearButtonImage(0),
+                DeckCall::SetButtonImage(0),
+                DeckCall::ClearButtonImage(1),
+                DeckCall::SetButtonImage(1),
+                DeckCall::ClearButtonImage(2),
+                DeckCall::ClearButtonImage(3),
+                DeckCall::ClearButtonImage(4),
+                DeckCall::ClearButtonImage(5),
+                DeckCall::Flush,
+            ]
+        );
+        assert!(deck.image(0).is_some());
+        assert!(deck.image(2).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_render_skips_unchanged_keys() {
+        let (state, deck, _dir) = attached_state().await;
+        deck.take_calls();
+
+        state.render_active_page().await.unwrap();
+
+        assert!(deck.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_render_inherited_buttons() {
+        let (state, deck, dir) = attached_state().await;
+        let manifest = "extends: common\npages:\n  main:\n    1:\n      image:\n        src: c.bmp\n      action:\n        command: \"true\"\n";
+        write_profile(dir.path(), "app", manifest, &["c.bmp"]);
+        state.set_profiles(ajam_profile::open_profiles(dir.path()).unwrap()).await;
+        let common_image = deck.image(0);
+
+        // Key 0 is inherited and its image is loaded from the common profile.
+        state.navigate_to_profile_or_default("app").await.unwrap();
+
+        assert_eq!(state.navigation.read().await.profile, "app");
+        assert_eq!(deck.image(0), common_image);
+        assert!(deck.image(1).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_set_brightness() {
+        let (state, deck, _dir) = attached_state().await;
+        deck.take_calls();
+
+        state.set_brightness(-10).await.unwrap();
+
+        assert_eq!(deck.calls(), vec![DeckCall::SetBrightness(90)]);
+    }
+}
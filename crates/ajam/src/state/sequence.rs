@@ -0,0 +1,206 @@
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+use ajam_profile::{Action, SequenceStep};
+use async_recursion::async_recursion;
+use colored::Colorize;
+use enigo::InputError;
+use thiserror::Error;
+use tokio::task;
+use tokio::time::sleep;
+
+use crate::{print_debug, print_error, print_info};
+
+use super::events::LazyPerformer;
+use super::navigation::NavigationError;
+use super::State;
+
+/// Input is the button or encoder an action was started from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Input {
+    Button(u8),
+    Encoder(u8),
+}
+
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Input::Button(key) => write!(f, "button {}", key),
+            Input::Encoder(dial) => write!(f, "encoder {}", dial),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ActionError {
+    #[error("input performer is not available")]
+    NoPerformer,
+
+    #[error("error pressing keys: {0}")]
+    Keys(#[from] InputError),
+
+    #[error("error running command: {0}")]
+    Command(String),
+
+    #[error("error navigating: {0}")]
+    Navigation(#[from] NavigationError),
+
+    #[error("step {step} failed: {source}")]
+    Step {
+        step: usize,
+        #[source]
+        source: Box<ActionError>,
+    },
+}
+
+impl State {
+    /// Performs the steps in order, stopping at the first failing one.
+    #[async_recursion]
+    pub(super) async fn run_sequence(
+        &self,
+        steps: &[SequenceStep],
+        repeat: u32,
+        performer: &mut LazyPerformer,
+    ) -> Result<(), ActionError> {
+        for _ in 0..repeat {
+            for (i, step) in steps.iter().enumerate() {
+                let action = match step {
+                    SequenceStep::Delay { delay_ms } => {
+                        sleep(Duration::from_millis(*delay_ms)).await;
+                        continue;
+                    }
+                    SequenceStep::Action(action) => action.clone(),
+                };
+                if let Err(e) = self.perform_action(action, performer, true).await {
+                    return Err(ActionError::Step {
+                        step: i + 1,
+                        source: Box::new(e),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the action started from the input in the background,
+    /// or cancels it if it is still running.
+    pub(super) async fn start_action(&self, input: Input, action: Action) {
+        let state = self.clone();
+        self.spawn_background(input, async move {
+            let mut performer = LazyPerformer::default();
+            if let Err(e) = state.perform_action(action, &mut performer, true).await {
+                print_error!("Action of {} failed: {}", input, e);
+            }
+        })
+        .await;
+    }
+
+    /// Runs the task started from the input in the background,
+    /// or cancels the one still running for the input instead.
+    pub(super) async fn spawn_background<F>(&self, input: Input, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let mut running = self.background_actions.lock().await;
+        running.retain(|_, handle| !handle.is_finished());
+        if let Some(handle) = running.remove(&input) {
+            handle.abort();
+            print_info!("Cancelled action of {}", input);
+            return;
+        }
+
+        print_debug!("Starting action of {} in the background", input);
+        running.insert(input, task::spawn(task));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::testing::{attached_state_with, eventually};
+
+    use super::*;
+
+    const SEQUENCE_MANIFEST: &str = r#"
+pages_order:
+  - main
+  - second
+device: akp03
+pages:
+  main:
+    0:
+      image:
+        src: a.bmp
+      action:
+        sequence:
+          - command: "true"
+          - delay_ms: 100
+          - navigate: second
+    1:
+      image:
+        src: b.bmp
+      action:
+        sequence:
+          - navigate: missing
+          - navigate: second
+  second: {}
+encoders: {}
+"#;
+
+    async fn button_action(state: &State, key: u8) -> Action {
+        state.get_button(key).await.unwrap().action(false).clone()
+    }
+
+    fn sequence_of(action: Action) -> (Vec<SequenceStep>, u32) {
+        match action {
+            Action::Sequence { sequence, repeat } => (sequence, repeat),
+            _ => panic!("expected a sequence"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sequence_runs_steps() {
+        let (state, _deck, _dir) = attached_state_with(SEQUENCE_MANIFEST).await;
+
+        let action = button_action(&state, 0).await;
+        state.start_action(Input::Button(0), action.clone()).await;
+        assert_eq!(state.navigation.read().await.page, "main");
+
+        let state = &state;
+        eventually(|| async move { state.navigation.read().await.page == "second" }).await;
+        eventually(|| async move {
+            state.background_actions.lock().await.values().all(|handle| handle.is_finished())
+        })
+        .await;
+
+        // Finished actions are forgotten when another one starts.
+        state.start_action(Input::Encoder(0), action).await;
+        let running = state.background_actions.lock().await;
+        assert_eq!(running.keys().collect::<Vec<_>>(), vec![&Input::Encoder(0)]);
+    }
+
+    #[tokio::test]
+    async fn test_sequence_cancel() {
+        let (state, _deck, _dir) = attached_state_with(SEQUENCE_MANIFEST).await;
+
+        let action = button_action(&state, 0).await;
+        state.start_action(Input::Button(0), action.clone()).await;
+        state.start_action(Input::Button(0), action).await;
+
+        sleep(Duration::from_millis(200)).await;
+        assert_eq!(state.navigation.read().await.page, "main");
+    }
+
+    #[tokio::test]
+    async fn test_sequence_reports_failed_step() {
+        let (state, _deck, _dir) = attached_state_with(SEQUENCE_MANIFEST).await;
+
+        let (steps, repeat) = sequence_of(button_action(&state, 1).await);
+        let result = state
+            .run_sequence(&steps, repeat, &mut LazyPerformer::default())
+            .await;
+
+        assert!(matches!(result, Err(ActionError::Step { step: 1, .. })));
+        assert_eq!(state.navigation.read().await.page, "main");
+    }
+}
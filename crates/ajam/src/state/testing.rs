@@ -0,0 +1,91 @@
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use ajam_profile::open_profiles;
+pub(crate) use ajam_profile::testing::TempDir;
+use ajazz_sdk::info::Kind;
+use image::{Rgb, RgbImage};
+
+use crate::deck::VirtualDeck;
+
+use super::connect::StateConnect;
+use super::State;
+
+pub(crate) const TEST_MANIFEST: &str = r#"
+pages_order:
+  - main
+  - second
+device: akp03
+pages:
+  main:
+    0:
+      image:
+        src: a.bmp
+      action:
+        navigate: second
+    1:
+      image:
+        src: b.bmp
+      action:
+        command: "true"
+  second:
+    0:
+      image:
+        src: b.bmp
+      action:
+        navigate: main
+encoders:
+  0:
+    minus:
+      keys: illumination_down
+    plus:
+      keys: illumination_up
+"#;
+
+/// Writes a profile with the manifest and solid color images into the profiles directory.
+pub(crate) fn write_profile(dir: &Path, name: &str, manifest: &str, images: &[&str]) {
+    let profile_path = dir.join(name);
+    std::fs::create_dir_all(&profile_path).unwrap();
+    std::fs::write(profile_path.join("manifest.yaml"), manifest).unwrap();
+    for (i, image) in images.iter().enumerate() {
+        let path = profile_path.join(image);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        RgbImage::from_pixel(8, 8, Rgb([i as u8 * 40, 0, 0]))
+            .save(path)
+            .unwrap();
+    }
+}
+
+/// Creates a state with the test profile attached to a virtual deck.
+pub(crate) async fn attached_state() -> (State, Arc<VirtualDeck>, TempDir) {
+    attached_state_with(TEST_MANIFEST).await
+}
+
+/// Creates a state with the given manifest as the common profile attached to a virtual deck.
+pub(crate) async fn attached_state_with(manifest: &str) -> (State, Arc<VirtualDeck>, TempDir) {
+    let dir = TempDir::new();
+    write_profile(dir.path(), "common", manifest, &["a.bmp", "b.bmp"]);
+
+    let state = State::with_profiles(dir.path().to_path_buf(), open_profiles(dir.path()).unwrap());
+    let deck = Arc::new(VirtualDeck::new(Kind::Akp03));
+    state.attach_deck(deck.clone()).await;
+
+    (state, deck, dir)
+}
+
+/// Waits until the condition becomes true, panicking after a second.
+pub(crate) async fn eventually<F, Fut>(mut condition: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    for _ in 0..100 {
+        if condition().await {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("condition was not met in time");
+}
@@ -0,0 +1,278 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use ajam_profile::{Action, Button, Toggle};
+use colored::Colorize;
+use tokio::task::JoinSet;
+use tokio::time::{interval, Instant};
+
+use crate::print_error;
+
+use super::events::{run_command, runs_in_background, LazyPerformer};
+use super::navigation::Location;
+use super::sequence::Input;
+use super::render::StateRender;
+use super::{Decks, State};
+
+const PROBE_TICK: Duration = Duration::from_millis(250);
+pub(super) const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn toggle_key(profile: &str, page: &str, key: u8) -> String {
+    format!("{}/{}/{}", profile, page, key)
+}
+
+impl State {
+    async fn active_toggle_key(&self, key: u8) -> String {
+        let navigation = self.navigation.read().await;
+        toggle_key(&navigation.profile, &navigation.page, key)
+    }
+
+    /// Returns the state of a toggle on the active page, toggles are off by default.
+    pub(super) async fn is_toggled(&self, key: u8) -> bool {
+        let location = self.navigation.read().await.location();
+        self.is_toggled_at(&location, key).await
+    }
+
+    /// Returns the state of a toggle on the page, toggles are off by default.
+    pub(super) async fn is_toggled_at(&self, location: &Location, key: u8) -> bool {
+        let toggle_key = toggle_key(&location.profile, &location.page, key);
+        self.toggles.read().await.get(&toggle_key).copied().unwrap_or(false)
+    }
+
+    /// Stores the state of a toggle, returns true if it changed.
+    async fn set_toggled(&self, toggle_key: String, toggle: &Toggle, on: bool) -> bool {
+        let previous = self.toggles.write().await.insert(toggle_key.clone(), on);
+        if previous.unwrap_or(false) == on {
+            return false;
+        }
+
+        if toggle.persist && self.state_file.is_some() {
+            self.persisted.lock().await.toggles.insert(toggle_key, on);
+            self.request_save();
+        }
+        true
+    }
+
+    /// Performs the action of the current toggle state and flips it once the action succeeded.
+    /// Actions running in the background flip it when they finish.
+    pub(super) async fn press_toggle(&self, key: u8, button: &Button, performer: &mut LazyPerformer) {
+        let Some(toggle) = button.toggle() else {
+            return;
+        };
+
+        let toggle_key = self.active_toggle_key(key).await;
+        let on = self.is_toggled(key).await;
+        let action = toggle.state(on).action.clone();
+        if !runs_in_background(&action) {
+            self.perform_toggle(toggle_key, toggle, on, action, performer).await;
+            return;
+        }
+
+        let state = self.clone();
+        let toggle = toggle.clone();
+        self.spawn_background(Input::Button(key), async move {
+            let mut performer = LazyPerformer::default();
+            state.perform_toggle(toggle_key, &toggle, on, action, &mut performer).await;
+        })
+        .await;
+    }
+
+    /// Performs the action of a toggle state, the toggle is flipped only if it succeeded.
+    async fn perform_toggle(
+        &self,
+        toggle_key: String,
+        toggle: &Toggle,
+        on: bool,
+        action: Action,
+        performer: &mut LazyPerformer,
+    ) {
+        if let Err(e) = self.perform_action(action, performer, true).await {
+            print_error!("error performing toggle action, keeping {}: {}", toggle_key, e);
+            return;
+        }
+
+        self.set_toggled(toggle_key, toggle, !on).await;
+        if let Err(e) = self.render_active_page().await {
+            print_error!("error rendering toggle: {}", e);
+        }
+    }
+}
+
+impl Decks {
+    /// Periodically runs the probes of toggles on the pages the decks show.
+    ///
+    /// A page shown on several decks is probed once, the decks showing it are rendered
+    /// again when one of its toggles changed.
+    pub async fn watch_toggle_probes(&self) {
+        let mut last_probes = HashMap::new();
+        let mut ticker = interval(PROBE_TICK);
+
+        loop {
+            ticker.tick().await;
+            let changed = self.probe_toggles(&mut last_probes).await;
+            self.render_pages(&changed).await;
+        }
+    }
+
+    /// Runs the due probes of the shown pages, returns the pages whose toggles changed.
+    async fn probe_toggles(&self, last_probes: &mut HashMap<String, Instant>) -> HashSet<Location> {
+        let mut probes = JoinSet::new();
+        for (location, page) in self.active_pages().await {
+            for (key, button) in page.indexed_buttons() {
+                let Some(toggle) = button.toggle() else {
+                    continue;
+                };
+                let Some(probe) = &toggle.probe else {
+                    continue;
+                };
+
+                let toggle_key = toggle_key(&location.profile, &location.page, key);
+                let probe_interval = Duration::from_millis(probe.interval_ms);
+                if let Some(last_probe) = last_probes.get(&toggle_key) {
+                    if last_probe.elapsed() < probe_interval {
+                        continue;
+                    }
+                }
+                last_probes.insert(toggle_key.clone(), Instant::now());
+
+                let location = location.clone();
+                let toggle = toggle.clone();
+                let command = probe.command.clone();
+                probes.spawn(async move {
+                    let on = run_probe(&command).await;
+                    (location, toggle_key, toggle, on)
+                });
+            }
+        }
+
+        let mut changed = HashSet::new();
+        while let Some(probed) = probes.join_next().await {
+            if let Ok((location, toggle_key, toggle, Some(on))) = probed {
+                if self.shared().set_toggled(toggle_key, &toggle, on).await {
+                    changed.insert(location);
+                }
+            }
+        }
+        changed
+    }
+}
+
+/// Runs the probe command, zero exit status means on. Probes not finishing in time are stopped.
+pub(super) async fn run_probe(command: &str) -> Option<bool> {
+    match run_command(command, Some(PROBE_TIMEOUT)).await {
+        Ok(output) => Some(output.status.success()),
+        Err(e) => {
+            print_error!("Failed to run probe {:?}: {}", command, e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ajam_profile::open_profiles;
+    use ajazz_sdk::info::Kind;
+    use ajazz_sdk::DeviceStateUpdate;
+
+    use crate::deck::{DeckCall, VirtualDeck};
+    use crate::state::persist::PersistedState;
+    use crate::state::testing::{attached_state_with, eventually, write_profile, TempDir};
+    use crate::state::connect::StateConnect;
+
+    use super::*;
+
+    const TOGGLE_MANIFEST: &str = r#"
+pages_order:
+  - main
+device: akp03
+pages:
+  main:
+    0:
+      toggle:
+        on:
+          image:
+            src: a.bmp
+          action:
+            command: "true"
+        off:
+          image:
+            src: b.bmp
+          action:
+            command: "true"
+        persist: true
+    1:
+      toggle:
+        on:
+          image:
+            src: a.bmp
+          action:
+            command: "true"
+        off:
+          image:
+            src: b.bmp
+          action:
+            command: "true"
+        probe:
+          command: "true"
+          interval_ms: 50
+    2:
+      toggle:
+        on:
+          action:
+            navigate: main
+        off:
+          action:
+            navigate: missing
+encoders: {}
+"#;
+
+    #[tokio::test]
+    async fn test_press_flips_toggle() {
+        let dir = TempDir::new();
+        write_profile(dir.path(), "common", TOGGLE_MANIFEST, &["a.bmp", "b.bmp"]);
+        let state_file = dir.path().join("state.json");
+        let profiles = open_profiles(dir.path()).unwrap();
+        let state = State::with_profiles(dir.path().to_path_buf(), profiles)
+            .with_state_file(state_file.clone());
+        let deck = Arc::new(VirtualDeck::new(Kind::Akp03));
+        state.attach_deck(deck.clone()).await;
+        let off_image = deck.image(0).unwrap();
+        deck.take_calls();
+
+        deck.send(DeviceStateUpdate::ButtonDown(0));
+        deck.send(DeviceStateUpdate::ButtonUp(0));
+
+        let (state, deck) = (&state, &deck);
+        eventually(|| async move { state.is_toggled(0).await }).await;
+        eventually(|| async move { deck.calls().contains(&DeckCall::SetButtonImage(0)) }).await;
+        assert_ne!(deck.image(0).unwrap(), off_image);
+
+        state.save_state().await;
+        let persisted = PersistedState::load(&state_file).unwrap();
+        assert_eq!(persisted.toggles.get("common/main/0"), Some(&true));
+    }
+
+    #[tokio::test]
+    async fn test_failed_action_keeps_toggle() {
+        let (state, _deck, _dir) = attached_state_with(TOGGLE_MANIFEST).await;
+
+        let button = state.get_button(2).await.unwrap();
+        state.press_toggle(2, &button, &mut LazyPerformer::default()).await;
+        assert!(!state.is_toggled(2).await);
+    }
+
+    #[tokio::test]
+    async fn test_probe_updates_toggle() {
+        let (state, _deck, _dir) = attached_state_with(TOGGLE_MANIFEST).await;
+        assert!(!state.is_toggled(1).await);
+
+        let decks = Decks::with_state(state.clone()).await;
+        let probes = tokio::spawn(async move { decks.watch_toggle_probes().await });
+
+        let state = &state;
+        eventually(|| async move { state.is_toggled(1).await }).await;
+        probes.abort();
+    }
+}
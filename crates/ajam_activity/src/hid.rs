@@ -0,0 +1,107 @@
+#![allow(non_upper_case_globals)]
+
+use std::os::raw::{c_char, c_void};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::Event;
+
+type IONotificationPortRef = *mut c_void;
+type IOIterator = u32;
+type IOService = u32;
+type KernReturn = i32;
+type CFDictionaryRef = *mut c_void;
+type CFRunLoopRef = *mut c_void;
+type CFRunLoopSourceRef = *mut c_void;
+type CFStringRef = *const c_void;
+
+const kIOMainPortDefault: u32 = 0;
+const kIOReturnSuccess: KernReturn = 0;
+const kIOFirstMatchNotification: &[u8] = b"IOServiceFirstMatch\0";
+const kIOHIDDeviceClass: &[u8] = b"IOHIDDevice\0";
+
+#[link(name = "IOKit", kind = "framework")]
+extern "C" {
+    fn IONotificationPortCreate(main_port: u32) -> IONotificationPortRef;
+    fn IONotificationPortGetRunLoopSource(port: IONotificationPortRef) -> CFRunLoopSourceRef;
+    fn IOServiceMatching(name: *const c_char) -> CFDictionaryRef;
+    fn IOServiceAddMatchingNotification(
+        port: IONotificationPortRef,
+        notification_type: *const c_char,
+        matching: CFDictionaryRef,
+        callback: extern "C" fn(*mut c_void, IOIterator),
+        context: *mut c_void,
+        iterator: *mut IOIterator,
+    ) -> KernReturn;
+    fn IOIteratorNext(iterator: IOIterator) -> IOService;
+    fn IOObjectRelease(object: IOService) -> KernReturn;
+}
+
+#[link(name = "CoreFoundation", kind = "framework")]
+extern "C" {
+    static kCFRunLoopDefaultMode: CFStringRef;
+    fn CFRunLoopGetCurrent() -> CFRunLoopRef;
+    fn CFRunLoopAddSource(run_loop: CFRunLoopRef, source: CFRunLoopSourceRef, mode: CFStringRef);
+    fn CFRunLoopRun();
+}
+
+/// Sends an event whenever a HID device is connected, decks are among them.
+///
+/// Devices are not opened, so no input monitoring permission is needed.
+pub(crate) fn start_hid_watcher(tx: mpsc::Sender<Event>) {
+    thread::spawn(move || unsafe {
+        let port = IONotificationPortCreate(kIOMainPortDefault);
+        if port.is_null() {
+            println!("Error creating IOKit notification port");
+            return;
+        }
+        CFRunLoopAddSource(
+            CFRunLoopGetCurrent(),
+            IONotificationPortGetRunLoopSource(port),
+            kCFRunLoopDefaultMode,
+        );
+
+        // The matching dictionary is consumed by the notification.
+        let matching = IOServiceMatching(kIOHIDDeviceClass.as_ptr() as *const c_char);
+        let context = Box::into_raw(Box::new(tx)) as *mut c_void;
+        let mut iterator: IOIterator = 0;
+        let status = IOServiceAddMatchingNotification(
+            port,
+            kIOFirstMatchNotification.as_ptr() as *const c_char,
+            matching,
+            handle_devices_added,
+            context,
+            &mut iterator,
+        );
+        if status != kIOReturnSuccess {
+            println!("Error adding HID device notification: {}", status);
+            return;
+        }
+        // Notifications are armed once the iterator is drained, the present devices are skipped.
+        drain(iterator);
+        CFRunLoopRun();
+    });
+}
+
+extern "C" fn handle_devices_added(context: *mut c_void, iterator: IOIterator) {
+    if unsafe { drain(iterator) } == 0 {
+        return;
+    }
+    let tx = unsafe { &*(context as *const mpsc::Sender<Event>) };
+    if let Err(e) = tx.send(Event::HidDeviceAdded) {
+        println!("Error sending event: {:?}", e);
+    }
+}
+
+/// Releases the devices of the iterator, returns how many there were.
+unsafe fn drain(iterator: IOIterator) -> usize {
+    let mut count = 0;
+    loop {
+        let device = IOIteratorNext(iterator);
+        if device == 0 {
+            return count;
+        }
+        IOObjectRelease(device);
+        count += 1;
+    }
+}
@@ -1,5 +1,6 @@
 mod monitor;
 mod coreaudio;
+mod hid;
 mod nsworkspace;
 
 pub use monitor::{Monitor, Event};
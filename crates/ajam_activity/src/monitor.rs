@@ -2,19 +2,26 @@ use std::sync::mpsc;
 use std::thread;
 
 use crate::coreaudio::start_coreaudio_listener;
+use crate::hid::start_hid_watcher;
 use crate::nsworkspace::{start_nsworkspace_listener, NSWorkspaceError};
 
 /// An event from the monitor.
 #[derive(Debug, Clone)]
 pub enum Event {
     AppChange(String),
+    /// The title of the focused window changed, holds the bundle id of its app and the title.
+    WindowTitleChange(String, String),
+    /// Displays were connected or disconnected, holds the names of the connected ones.
+    DisplaysChange(Vec<String>),
+    /// A HID device was connected, it may be a deck.
+    HidDeviceAdded,
     AudioOutputChange(String),
     AudioInputChange(String)
 }
 
 /// A monitor for system events.
 ///
-/// This monitor listens for events from the core audio and workspace APIs.
+/// This monitor listens for events from the core audio, IOKit and workspace APIs.
 /// It is designed to be used in the main thread.
 pub struct Monitor {
     event_tx: mpsc::Sender<Event>,
@@ -40,6 +47,7 @@ impl Monitor {
             }
         });
 
+        start_hid_watcher(self.event_tx.clone());
         start_nsworkspace_listener(self.event_tx)
     }
 }
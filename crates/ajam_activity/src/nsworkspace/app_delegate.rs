@@ -41,10 +41,28 @@ impl AppDelegate {
                 }
             }
 
+            extern "C" fn update_displays(
+                this: &Object,
+                _sel: objc::runtime::Sel,
+                _notification: id,
+            ) {
+                unsafe {
+                    let state_ptr: *mut c_void = *this.get_ivar("_rustState");
+                    let state = &*(state_ptr as *const AppState);
+                    if let Err(e) = state.notify_displays() {
+                        println!("❌ Error in update_displays: {:?}", e);
+                    }
+                }
+            }
+
             decl.add_method(
                 sel!(updateActiveApplication:),
                 update_active_application as extern "C" fn(&Object, _, _),
             );
+            decl.add_method(
+                sel!(updateDisplays:),
+                update_displays as extern "C" fn(&Object, _, _),
+            );
 
             decl.register();
 
@@ -1,4 +1,4 @@
-use cocoa::base::id;
+use cocoa::base::{id, nil};
 use objc::{class, msg_send, sel, sel_impl};
 use std::os::raw::c_char;
 use std::sync::mpsc;
@@ -56,7 +56,34 @@ impl AppState {
         }
     }
 
+    pub(crate) fn notify_displays(&self) -> Result<(), NSWorkspaceError> {
+        let mut names = Vec::new();
+        unsafe {
+            let screens: id = msg_send![class!(NSScreen), screens];
+            let count: usize = msg_send![screens, count];
+            for i in 0..count {
+                let screen: id = msg_send![screens, objectAtIndex: i];
+                let name: id = msg_send![screen, localizedName];
+                if name.is_null() {
+                    continue;
+                }
+                let utf8: *const c_char = msg_send![name, UTF8String];
+                if utf8.is_null() {
+                    continue;
+                }
+                let cstr = std::ffi::CStr::from_ptr(utf8);
+                names.push(cstr.to_str().map_err(NSWorkspaceError::ConvertStringError)?.to_string());
+            }
+        }
+
+        self.event_tx
+            .send(Event::DisplaysChange(names))
+            .map_err(NSWorkspaceError::SendEventError)
+    }
+
     pub(crate) fn setup_notifications(&self, delegate: id) -> Result<(), NSWorkspaceError> {
+        self.notify_displays()?;
+
         unsafe {
             let workspace: id = msg_send![class!(NSWorkspace), sharedWorkspace];
             let frontmost_app: id = msg_send![workspace, frontmostApplication];
@@ -85,6 +112,14 @@ impl AppState {
                 selector:sel!(updateActiveApplication:)
                 name:app_active
                 object:workspace];
+
+            let notification_center: id = msg_send![class!(NSNotificationCenter), defaultCenter];
+            let screens_changed = make_nsstring("NSApplicationDidChangeScreenParametersNotification");
+            let _: () = msg_send![notification_center,
+                addObserver:delegate
+                selector:sel!(updateDisplays:)
+                name:screens_changed
+                object:nil];
         }
 
         Ok(())
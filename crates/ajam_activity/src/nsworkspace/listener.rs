@@ -2,9 +2,10 @@ use std::sync::mpsc;
 
 use crate::monitor::Event;
 
-use super::{app_delegate::AppDelegate, NSWorkspaceError};
+use super::{app_delegate::AppDelegate, window_title::start_window_title_watcher, NSWorkspaceError};
 
 pub(crate) fn start_nsworkspace_listener(tx: mpsc::Sender<Event>) -> Result<(), NSWorkspaceError> {
+    start_window_title_watcher(tx.clone());
     AppDelegate::new(tx)?.start_listening();
     Ok(())
 }
@@ -2,6 +2,7 @@ mod app_delegate;
 mod app_state;
 mod util;
 mod listener;
+mod window_title;
 
 use std::str::Utf8Error;
 
@@ -0,0 +1,111 @@
+use cocoa::base::{id, nil};
+use cocoa::foundation::NSAutoreleasePool;
+use objc::{class, msg_send, sel, sel_impl};
+use std::os::raw::c_char;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use crate::Event;
+
+use super::util::make_nsstring;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+const K_CG_WINDOW_LIST_OPTION_ON_SCREEN_ONLY: u32 = 1 << 0;
+const K_CG_WINDOW_LIST_EXCLUDE_DESKTOP_ELEMENTS: u32 = 1 << 4;
+const K_CG_NULL_WINDOW_ID: u32 = 0;
+
+#[link(name = "CoreGraphics", kind = "framework")]
+extern "C" {
+    fn CGWindowListCopyWindowInfo(option: u32, relative_to_window: u32) -> id;
+}
+
+/// Polls the title of the focused window and sends it with the bundle id
+/// of its app when either changes.
+///
+/// Titles are only readable with the screen recording permission,
+/// without it no events are sent.
+pub(crate) fn start_window_title_watcher(tx: mpsc::Sender<Event>) {
+    thread::spawn(move || {
+        // Reset while no title is readable, so the window is reported again once it is.
+        let mut last_focused: Option<(String, String)> = None;
+        loop {
+            let focused = unsafe { focused_window_title() };
+            if focused != last_focused {
+                last_focused = focused.clone();
+                if let Some((bundle_id, title)) = focused {
+                    if tx.send(Event::WindowTitleChange(bundle_id, title)).is_err() {
+                        return;
+                    }
+                }
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    });
+}
+
+#[allow(improper_ctypes, unexpected_cfgs)]
+unsafe fn focused_window_title() -> Option<(String, String)> {
+    let pool = NSAutoreleasePool::new(nil);
+    let title = frontmost_window_title();
+    let _: () = msg_send![pool, drain];
+    title
+}
+
+/// Returns the bundle id of the frontmost app and the title of its focused window.
+#[allow(improper_ctypes, unexpected_cfgs)]
+unsafe fn frontmost_window_title() -> Option<(String, String)> {
+    let workspace: id = msg_send![class!(NSWorkspace), sharedWorkspace];
+    let app: id = msg_send![workspace, frontmostApplication];
+    if app.is_null() {
+        return None;
+    }
+    let pid: i32 = msg_send![app, processIdentifier];
+    let bundle_id: id = msg_send![app, bundleIdentifier];
+    let bundle_id = nsstring_to_string(bundle_id)?;
+
+    // The list is ordered front to back, the first normal window of the app is the focused one.
+    let windows = CGWindowListCopyWindowInfo(
+        K_CG_WINDOW_LIST_OPTION_ON_SCREEN_ONLY | K_CG_WINDOW_LIST_EXCLUDE_DESKTOP_ELEMENTS,
+        K_CG_NULL_WINDOW_ID,
+    );
+    if windows.is_null() {
+        return None;
+    }
+    let windows: id = msg_send![windows, autorelease];
+
+    let owner_key = make_nsstring("kCGWindowOwnerPID");
+    let layer_key = make_nsstring("kCGWindowLayer");
+    let name_key = make_nsstring("kCGWindowName");
+    let count: usize = msg_send![windows, count];
+    for i in 0..count {
+        let window: id = msg_send![windows, objectAtIndex: i];
+        let owner: id = msg_send![window, objectForKey: owner_key];
+        let layer: id = msg_send![window, objectForKey: layer_key];
+        if owner.is_null() || layer.is_null() {
+            continue;
+        }
+        let owner: i32 = msg_send![owner, intValue];
+        let layer: i32 = msg_send![layer, intValue];
+        if owner != pid || layer != 0 {
+            continue;
+        }
+
+        let name: id = msg_send![window, objectForKey: name_key];
+        return nsstring_to_string(name).map(|title| (bundle_id, title));
+    }
+    None
+}
+
+#[allow(unexpected_cfgs)]
+unsafe fn nsstring_to_string(string: id) -> Option<String> {
+    if string.is_null() {
+        return None;
+    }
+    let utf8: *const c_char = msg_send![string, UTF8String];
+    if utf8.is_null() {
+        return None;
+    }
+    std::ffi::CStr::from_ptr(utf8).to_str().ok().map(str::to_string)
+}
@@ -1,4 +1,4 @@
-use enigo::{Enigo, InputResult, NewConError, Settings};
+use enigo::{Enigo, InputResult, Keyboard, NewConError, Settings};
 
 use crate::KeyCombo;
 
@@ -30,4 +30,9 @@ impl Performer {
     pub fn release(&mut self, key_combo: &KeyCombo) -> InputResult<()> {
         key_combo.release(&mut self.enigo)
     }
+
+    /// Types the text as is, regardless of the keyboard layout.
+    pub fn text(&mut self, text: &str) -> InputResult<()> {
+        self.enigo.text(text)
+    }
 }
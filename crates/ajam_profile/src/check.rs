@@ -0,0 +1,812 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use ajam_keypress::KeyCombo;
+use regex::Regex;
+use serde_yaml::Value;
+
+use crate::image::{is_supported_image, parse_color, ButtonImage, GeneratedImage};
+use crate::manifest::{Action, ButtonKind, DeviceTarget, Manifest, NavigationCommand, SequenceStep};
+use crate::condition::Condition;
+use crate::matcher::AppPattern;
+use crate::profile::{resolve_extends, MANIFEST_FILE_NAME};
+use crate::ProfileError;
+
+const DEFAULT_IMAGE: &str = "default";
+
+/// MANIFEST_SECTIONS are the top level keys the daemon reads, others are ignored.
+const MANIFEST_SECTIONS: &[&str] = &[
+    "pages_order",
+    "extends",
+    "match",
+    "when",
+    "device",
+    "background",
+    "fit",
+    "navigation",
+    "last_page",
+    "pages",
+    "encoders",
+];
+
+/// Severity is the severity of a diagnostic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// Diagnostic is a single problem found in a profile.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: PathBuf,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+            if let Some(column) = self.column {
+                write!(f, ":{}", column)?;
+            }
+        }
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, ": {}: {}", severity, self.message)
+    }
+}
+
+/// Checks every profile in the directory.
+pub fn check_profiles<P: AsRef<Path>>(dir: P) -> Result<Vec<Diagnostic>, ProfileError> {
+    let mut paths = Vec::new();
+    for entry in dir.as_ref().read_dir()? {
+        let path = entry?.path();
+        if path.is_dir() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    Ok(paths.iter().flat_map(|path| check_profile(path)).collect())
+}
+
+/// Checks a single profile directory.
+pub fn check_profile(path: &Path) -> Vec<Diagnostic> {
+    let manifest_path = path.join(MANIFEST_FILE_NAME);
+    match fs::read_to_string(&manifest_path) {
+        Ok(source) => check_manifest(&source, &manifest_path, path),
+        Err(e) => vec![Diagnostic {
+            severity: Severity::Error,
+            file: manifest_path,
+            line: None,
+            column: None,
+            message: format!("unable to read manifest: {}", e),
+        }],
+    }
+}
+
+/// Checks manifest source. Image paths are resolved relative to `profile_path`.
+pub fn check_manifest(source: &str, manifest_path: &Path, profile_path: &Path) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        file: manifest_path.to_path_buf(),
+        profile_path: profile_path.to_path_buf(),
+        locator: Locator::new(source),
+        diagnostics: Vec::new(),
+    };
+
+    let value: Value = match serde_yaml::from_str(source) {
+        Ok(value) => value,
+        Err(e) => {
+            checker.yaml_error(&e);
+            return checker.diagnostics;
+        }
+    };
+    checker.check_sections(&value);
+    checker.check_key_combos(&value, &mut Vec::new());
+
+    match serde_yaml::from_str::<Manifest>(source) {
+        Ok(manifest) => {
+            if let Some(resolved) = checker.resolve(&manifest) {
+                checker.check(&manifest, &resolved);
+            }
+        }
+        Err(e) => {
+            if checker.diagnostics.is_empty() {
+                checker.yaml_error(&e);
+            }
+        }
+    }
+
+    checker.diagnostics
+}
+
+struct Checker {
+    file: PathBuf,
+    profile_path: PathBuf,
+    locator: Locator,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker {
+    fn report(&mut self, severity: Severity, path: &[&str], message: String) {
+        let location = self.locator.locate(path);
+        self.diagnostics.push(Diagnostic {
+            severity,
+            file: self.file.clone(),
+            line: location.map(|(line, _)| line),
+            column: location.map(|(_, column)| column),
+            message,
+        });
+    }
+
+    fn error(&mut self, path: &[&str], message: String) {
+        self.report(Severity::Error, path, message);
+    }
+
+    fn warning(&mut self, path: &[&str], message: String) {
+        self.report(Severity::Warning, path, message);
+    }
+
+    fn yaml_error(&mut self, e: &serde_yaml::Error) {
+        let message = e.to_string();
+        let message = message.split(" at line ").next().unwrap_or(&message);
+        let location = e.location();
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            file: self.file.clone(),
+            line: location.as_ref().map(|l| l.line()),
+            column: location.as_ref().map(|l| l.column()),
+            message: message.to_string(),
+        });
+    }
+
+    /// Reports unknown top level keys, e.g. sections for parts of the device that are not supported.
+    fn check_sections(&mut self, value: &Value) {
+        let Value::Mapping(mapping) = value else {
+            return;
+        };
+        for key in mapping.keys().filter_map(yaml_key) {
+            if !MANIFEST_SECTIONS.contains(&key.as_str()) {
+                self.warning(&[key.as_str()], format!("unknown section '{}' is ignored", key));
+            }
+        }
+    }
+
+    fn check_key_combos(&mut self, value: &Value, path: &mut Vec<String>) {
+        match value {
+            Value::Mapping(mapping) => {
+                for (key, value) in mapping {
+                    let key = yaml_key(key);
+                    if let (Some("keys"), Value::String(combo)) = (key.as_deref(), value) {
+                        if let Err(e) = KeyCombo::from_str(combo) {
+                            path.push("keys".to_string());
+                            let segments: Vec<&str> = path.iter().map(String::as_str).collect();
+                            self.error(&segments, format!("invalid key combo '{}': {}", combo, e));
+                            path.pop();
+                        }
+                        continue;
+                    }
+                    path.push(key.unwrap_or_default());
+                    self.check_key_combos(value, path);
+                    path.pop();
+                }
+            }
+            Value::Sequence(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    path.push(format!("[{}]", i));
+                    self.check_key_combos(item, path);
+                    path.pop();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Applies the profiles the manifest extends, read from the sibling profile directories.
+    fn resolve(&mut self, manifest: &Manifest) -> Option<Manifest> {
+        let profiles_dir = self.profile_path.parent().unwrap_or(Path::new("")).to_path_buf();
+        let name = self.profile_path.file_name().unwrap_or_default().to_string_lossy();
+        let resolved = resolve_extends(&name, manifest, |parent| {
+            let path = profiles_dir.join(parent);
+            if !path.join(MANIFEST_FILE_NAME).is_file() {
+                return Err(ProfileError::ExtendedProfileNotFound(parent.to_string()));
+            }
+            Ok((Manifest::from_file(path.join(MANIFEST_FILE_NAME))?, path))
+        });
+
+        match resolved {
+            Ok(resolved) => Some(resolved),
+            Err(e) => {
+                let message = match e {
+                    ProfileError::ExtendedProfileNotFound(parent) => {
+                        format!("extended profile '{}' not found", parent)
+                    }
+                    ProfileError::InheritanceCycle(chain) => {
+                        format!("profile inheritance cycle: {}", chain)
+                    }
+                    e => format!("unable to read extended profile: {}", e),
+                };
+                self.error(&["extends"], message);
+                None
+            }
+        }
+    }
+
+    /// Checks what the manifest declares, `resolved` is the manifest with what it inherits.
+    fn check(&mut self, manifest: &Manifest, resolved: &Manifest) {
+        let kind = DeviceTarget::parse(&resolved.device).map(|target| target.kind);
+        if kind.is_none() {
+            self.error(&["device"], format!("unknown device '{}'", resolved.device));
+        }
+        if let (Some(navigation), Some(kind)) = (&manifest.navigation, kind) {
+            for (name, key) in navigation.keys() {
+                if key >= kind.key_count() {
+                    self.error(
+                        &["navigation", name],
+                        format!(
+                            "navigation key {} is out of range, {} has {} keys",
+                            key,
+                            resolved.device,
+                            kind.key_count()
+                        ),
+                    );
+                }
+            }
+        }
+        if let Some(background) = &manifest.background {
+            if parse_color(background).is_none() {
+                self.error(&["background"], format!("invalid color '{}'", background));
+            }
+        }
+        for (i, app) in manifest.app_match.apps.iter().enumerate() {
+            if AppPattern::parse(app).is_err() {
+                let item = format!("[{}]", i);
+                self.error(&["match", "apps", &item], format!("invalid app pattern '{}'", app));
+            }
+        }
+        if let Some(title) = &manifest.app_match.title {
+            if Regex::new(title).is_err() {
+                self.error(&["match", "title"], format!("invalid title pattern '{}'", title));
+            }
+        }
+        if let Some(when) = &manifest.when {
+            self.check_condition(when, &["when"]);
+        }
+
+        for (i, page_name) in manifest.pages_order.iter().enumerate() {
+            if !resolved.pages.contains_key(page_name) {
+                let item = format!("[{}]", i);
+                self.error(
+                    &["pages_order", &item],
+                    format!("page '{}' is listed in pages_order but not defined", page_name),
+                );
+            }
+        }
+
+        let mut page_names: Vec<&String> = manifest.pages.keys().collect();
+        page_names.sort();
+
+        for page_name in page_names {
+            if resolved.page_index(page_name).is_none() {
+                self.error(
+                    &["pages", page_name],
+                    format!("page '{}' is missing from pages_order", page_name),
+                );
+            }
+
+            let page = &manifest.pages[page_name];
+            if let Some(when) = &page.when {
+                self.check_condition(when, &["pages", page_name, "when"]);
+            }
+            let mut buttons: Vec<_> = page.buttons.iter().collect();
+            buttons.sort_by_key(|(index, _)| (index.parse::<u8>().ok(), index.as_str()));
+
+            for (index, button) in buttons {
+                let button_path = ["pages", page_name.as_str(), index.as_str()];
+                let has_display = match index.parse::<u8>() {
+                    Err(_) => {
+                        self.error(&button_path, format!("invalid button index '{}'", index));
+                        true
+                    }
+                    Ok(index) => match kind {
+                        Some(kind) if index >= kind.key_count() => {
+                            self.error(
+                                &button_path,
+                                format!(
+                                    "button {} is out of range, {} has {} keys",
+                                    index,
+                                    resolved.device,
+                                    kind.key_count()
+                                ),
+                            );
+                            true
+                        }
+                        Some(kind) => index < kind.display_key_count(),
+                        None => true,
+                    },
+                };
+                let has_image = button.image(false).is_some() || button.image(true).is_some();
+                if !has_display && has_image {
+                    self.warning(
+                        &button_path,
+                        format!("button {} has no display, its image is ignored", index),
+                    );
+                }
+
+                match &button.kind {
+                    ButtonKind::Static { image, action } => {
+                        let image_path = [button_path.as_slice(), &["image"]].concat();
+                        self.check_image(image, &image_path);
+                        let action_path = [button_path.as_slice(), &["action"]].concat();
+                        self.check_action(resolved, action, &action_path);
+                    }
+                    ButtonKind::Toggle { toggle } => {
+                        for (state, name) in [(&toggle.on, "on"), (&toggle.off, "off")] {
+                            let state_path = [button_path.as_slice(), &["toggle", name]].concat();
+                            if let Some(image) = &state.image {
+                                let image_path = [state_path.as_slice(), &["image"]].concat();
+                                self.check_image(image, &image_path);
+                            }
+                            let action_path = [state_path.as_slice(), &["action"]].concat();
+                            self.check_action(resolved, &state.action, &action_path);
+                        }
+                    }
+                    ButtonKind::Plain { action } => {
+                        let action_path = [button_path.as_slice(), &["action"]].concat();
+                        self.check_action(resolved, action, &action_path);
+                    }
+                }
+                if let Some(long_press) = &button.long_press {
+                    let action_path = [button_path.as_slice(), &["long_press", "action"]].concat();
+                    self.check_action(resolved, &long_press.action, &action_path);
+                }
+                if let Some(double_tap) = &button.double_tap {
+                    let action_path = [button_path.as_slice(), &["double_tap", "action"]].concat();
+                    self.check_action(resolved, &double_tap.action, &action_path);
+                }
+            }
+        }
+
+        let mut encoders: Vec<_> = manifest.encoders.iter().collect();
+        encoders.sort_by_key(|(index, _)| **index);
+        for (index, actions) in encoders {
+            let index_key = index.to_string();
+            let encoder_path = ["encoders", index_key.as_str()];
+            self.check_action(resolved, &actions.plus, &[encoder_path.as_slice(), &["plus"]].concat());
+            self.check_action(resolved, &actions.minus, &[encoder_path.as_slice(), &["minus"]].concat());
+            if let Some(click) = &actions.click {
+                self.check_action(resolved, click, &[encoder_path.as_slice(), &["click"]].concat());
+            }
+        }
+    }
+
+    fn check_action(&mut self, manifest: &Manifest, action: &Action, path: &[&str]) {
+        match action {
+            Action::Navigate { navigate }
+                if navigate.starts_with('@') && NavigationCommand::parse(navigate).is_none() =>
+            {
+                let navigate_path = [path, &["navigate"]].concat();
+                self.error(&navigate_path, format!("unknown navigation command '{}'", navigate));
+            }
+            Action::Navigate { navigate }
+                if NavigationCommand::parse(navigate).is_none()
+                    && !manifest.pages.contains_key(navigate) =>
+            {
+                let navigate_path = [path, &["navigate"]].concat();
+                self.error(
+                    &navigate_path,
+                    format!("navigate target '{}' is not a page of this profile", navigate),
+                );
+            }
+            Action::Sequence { sequence, .. } => {
+                for (i, step) in sequence.iter().enumerate() {
+                    if let SequenceStep::Action(action) = step {
+                        let item = format!("[{}]", i);
+                        let step_path = [path, &["sequence", item.as_str()]].concat();
+                        self.check_action(manifest, action, &step_path);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn check_condition(&mut self, condition: &Condition, path: &[&str]) {
+        let patterns = [
+            (&condition.audio_output, "audio_output"),
+            (&condition.audio_input, "audio_input"),
+            (&condition.display, "display"),
+        ];
+        for (pattern, name) in patterns {
+            if let Some(pattern) = pattern {
+                if AppPattern::parse(pattern).is_err() {
+                    self.error(&[path, &[name]].concat(), format!("invalid pattern '{}'", pattern));
+                }
+            }
+        }
+        for (name, conditions) in [("all", &condition.all), ("any", &condition.any)] {
+            for (i, condition) in conditions.iter().enumerate() {
+                let item = format!("[{}]", i);
+                self.check_condition(condition, &[path, &[name, item.as_str()]].concat());
+            }
+        }
+        if let Some(condition) = &condition.not {
+            self.check_condition(condition, &[path, &["not"]].concat());
+        }
+    }
+
+    fn check_image(&mut self, image: &ButtonImage, path: &[&str]) {
+        match image {
+            ButtonImage::Source { src } => {
+                self.check_image_file(src, &[path, &["src"]].concat());
+            }
+            ButtonImage::AudioInput { audio_input } => {
+                self.check_image_map(audio_input, &[path, &["audio_input"]].concat());
+            }
+            ButtonImage::AudioOutput { audio_output } => {
+                self.check_image_map(audio_output, &[path, &["audio_output"]].concat());
+            }
+            ButtonImage::Command { images, .. } => {
+                self.check_image_map(images, &[path, &["images"]].concat());
+            }
+            ButtonImage::Generated(spec) => self.check_generated_image(spec, path),
+        }
+    }
+
+    fn check_generated_image(&mut self, spec: &GeneratedImage, path: &[&str]) {
+        if let (None, Some(background)) = (spec.background_color(), &spec.background) {
+            self.check_image_file(background, &[path, &["background"]].concat());
+        }
+        if let Some(icon) = &spec.icon {
+            self.check_image_file(icon, &[path, &["icon"]].concat());
+        }
+        if parse_color(&spec.color).is_none() {
+            self.error(
+                &[path, &["color"]].concat(),
+                format!("invalid color '{}'", spec.color),
+            );
+        }
+        if let Some(font) = &spec.font {
+            if !self.profile_path.join(font).is_file() {
+                self.error(&[path, &["font"]].concat(), format!("font file '{}' not found", font));
+            }
+        }
+    }
+
+    fn check_image_map(&mut self, images: &HashMap<String, String>, path: &[&str]) {
+        if !images.contains_key(DEFAULT_IMAGE) {
+            self.error(path, format!("image map has no '{}' key", DEFAULT_IMAGE));
+        }
+
+        let mut entries: Vec<_> = images.iter().collect();
+        entries.sort();
+        for (key, src) in entries {
+            self.check_image_file(src, &[path, &[key.as_str()]].concat());
+        }
+    }
+
+    fn check_image_file(&mut self, src: &str, path: &[&str]) {
+        let file_path = self.profile_path.join(src);
+        if !file_path.is_file() {
+            self.error(path, format!("image file '{}' not found", src));
+        } else if !is_supported_image(&file_path) {
+            self.warning(path, format!("image file '{}' has an unknown format", src));
+        }
+    }
+}
+
+fn yaml_key(key: &Value) -> Option<String> {
+    match key {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// Locator maps key paths of a block-style YAML document to their positions.
+///
+/// Sequence items are addressed as `[index]`. Flow-style collections
+/// are not indexed, lookups fall back to the closest indexed parent.
+struct Locator {
+    entries: HashMap<Vec<String>, (usize, usize)>,
+}
+
+impl Locator {
+    fn new(source: &str) -> Self {
+        let mut entries = HashMap::new();
+        let mut stack: Vec<(usize, String)> = Vec::new();
+        let mut sequence_counters: HashMap<Vec<String>, usize> = HashMap::new();
+
+        for (line_index, line) in source.lines().enumerate() {
+            let content = strip_comment(line);
+            let trimmed = content.trim_start();
+            if trimmed.is_empty() || trimmed.starts_with("---") {
+                continue;
+            }
+
+            let mut indent = content.len() - trimmed.len();
+            let mut rest = trimmed;
+
+            while let Some(item) = rest.strip_prefix('-').filter(|r| r.is_empty() || r.starts_with(' ')) {
+                while stack.last().is_some_and(|(i, _)| *i >= indent) {
+                    stack.pop();
+                }
+                let parent: Vec<String> = stack.iter().map(|(_, k)| k.clone()).collect();
+                let counter = sequence_counters.entry(parent.clone()).or_insert(0);
+                let key = format!("[{}]", counter);
+                *counter += 1;
+
+                let mut path = parent;
+                path.push(key.clone());
+                entries.insert(path, (line_index + 1, indent + 1));
+                stack.push((indent, key));
+
+                let item_trimmed = item.trim_start();
+                indent += 1 + (item.len() - item_trimmed.len());
+                rest = item_trimmed;
+            }
+
+            let Some(key) = parse_key(rest) else {
+                continue;
+            };
+
+            while stack.last().is_some_and(|(i, _)| *i >= indent) {
+                stack.pop();
+            }
+            stack.push((indent, key));
+            let path: Vec<String> = stack.iter().map(|(_, k)| k.clone()).collect();
+            sequence_counters.remove(&path);
+            entries.insert(path, (line_index + 1, indent + 1));
+        }
+
+        Self { entries }
+    }
+
+    fn locate(&self, path: &[&str]) -> Option<(usize, usize)> {
+        let mut path: Vec<String> = path.iter().map(|s| s.to_string()).collect();
+        loop {
+            if let Some(location) = self.entries.get(&path) {
+                return Some(*location);
+            }
+            path.pop()?;
+        }
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quote: Option<char> = None;
+    let mut previous = ' ';
+    for (i, ch) in line.char_indices() {
+        match quote {
+            Some(q) if ch == q => quote = None,
+            Some(_) => {}
+            None if ch == '"' || ch == '\'' => quote = Some(ch),
+            None if ch == '#' && previous.is_whitespace() => return &line[..i],
+            None => {}
+        }
+        previous = ch;
+    }
+    line
+}
+
+fn parse_key(content: &str) -> Option<String> {
+    if let Some(quoted) = content.strip_prefix('"').or_else(|| content.strip_prefix('\'')) {
+        let quote = content.chars().next()?;
+        let end = quoted.find(quote)?;
+        let after = quoted[end + 1..].trim_start();
+        return after.starts_with(':').then(|| quoted[..end].to_string());
+    }
+
+    let mut search_from = 0;
+    while let Some(pos) = content[search_from..].find(':') {
+        let pos = search_from + pos;
+        let after = &content[pos + 1..];
+        if after.is_empty() || after.starts_with(' ') {
+            let key = content[..pos].trim_end();
+            return (!key.is_empty()).then(|| key.to_string());
+        }
+        search_from = pos + 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TempDir;
+
+    use super::*;
+
+    const MANIFEST: &str = r#"pages_order:
+  - main
+  - missing
+
+device: akp03
+
+pages:
+  main:
+    0:
+      image:
+        src: main/terminal.jpg
+      action:
+        keys: ctrl+foo
+    9:
+      image:
+        audio_output:
+          "Speakers": main/terminal.jpg
+      action:
+        navigate: nowhere
+  extra: {}
+
+encoders: {}
+"#;
+
+    fn check(source: &str) -> Vec<Diagnostic> {
+        let dir = std::env::temp_dir();
+        check_manifest(source, &dir.join(MANIFEST_FILE_NAME), &dir.join("ajam-missing-profile"))
+    }
+
+    fn find<'a>(diagnostics: &'a [Diagnostic], text: &str) -> &'a Diagnostic {
+        diagnostics
+            .iter()
+            .find(|d| d.message.contains(text))
+            .unwrap_or_else(|| panic!("no diagnostic containing '{}' in {:#?}", text, diagnostics))
+    }
+
+    #[test]
+    fn test_invalid_key_combo() {
+        let diagnostics = check(MANIFEST);
+        assert_eq!(diagnostics.len(), 1);
+        let diagnostic = find(&diagnostics, "invalid key combo 'ctrl+foo'");
+        assert_eq!(diagnostic.line, Some(13));
+        assert_eq!(diagnostic.column, Some(9));
+    }
+
+    #[test]
+    fn test_semantic_errors() {
+        let source = MANIFEST.replace("ctrl+foo", "ctrl+c");
+        let diagnostics = check(&source);
+
+        assert_eq!(find(&diagnostics, "'missing' is listed in pages_order").line, Some(3));
+        assert_eq!(find(&diagnostics, "'extra' is missing from pages_order").line, Some(20));
+        assert_eq!(find(&diagnostics, "button 9 is out of range").line, Some(14));
+        assert_eq!(find(&diagnostics, "image map has no 'default' key").line, Some(16));
+        assert_eq!(find(&diagnostics, "navigate target 'nowhere'").line, Some(19));
+        assert_eq!(find(&diagnostics, "image file 'main/terminal.jpg' not found").line, Some(11));
+        assert!(diagnostics.iter().all(Diagnostic::is_error));
+    }
+
+    #[test]
+    fn test_unknown_device() {
+        let source = MANIFEST.replace("ctrl+foo", "ctrl+c").replace("akp03", "akp999");
+        let diagnostics = check(&source);
+        assert_eq!(find(&diagnostics, "unknown device 'akp999'").line, Some(5));
+    }
+
+    #[test]
+    fn test_button_without_display() {
+        let source = MANIFEST
+            .replace("ctrl+foo", "ctrl+c")
+            .replace("    9:\n", "    7:\n")
+            .replace("  extra: {}\n", "  extra:\n    8:\n      action:\n        keys: cmd+c\n");
+        let diagnostics = check(&source);
+
+        let diagnostic = find(&diagnostics, "button 7 has no display");
+        assert!(!diagnostic.is_error());
+        assert_eq!(diagnostic.line, Some(14));
+        assert!(!diagnostics.iter().any(|d| d.message.contains("button 8")));
+    }
+
+    #[test]
+    fn test_unknown_section() {
+        let source = MANIFEST
+            .replace("ctrl+foo", "ctrl+c")
+            .replace("encoders: {}\n", "encoders: {}\ntouch_strip:\n  action:\n    keys: cmd+c\n");
+        let diagnostics = check(&source);
+
+        let diagnostic = find(&diagnostics, "unknown section 'touch_strip' is ignored");
+        assert!(!diagnostic.is_error());
+        assert_eq!(diagnostic.line, Some(23));
+    }
+
+    #[test]
+    fn test_navigation() {
+        let source = MANIFEST
+            .replace("ctrl+foo", "ctrl+c")
+            .replace("device: akp03", "device: akp03\nnavigation:\n  back: 12")
+            .replace("navigate: nowhere", "navigate: \"@up\"");
+        let diagnostics = check(&source);
+
+        assert_eq!(find(&diagnostics, "navigation key 12 is out of range").line, Some(7));
+        assert!(diagnostics.iter().any(|d| d.message.contains("unknown navigation command '@up'")));
+        assert!(!diagnostics.iter().any(|d| d.message.contains("not a page")));
+    }
+
+    #[test]
+    fn test_invalid_background() {
+        let source = MANIFEST
+            .replace("ctrl+foo", "ctrl+c")
+            .replace("device: akp03", "device: akp03\nbackground: \"#12\"");
+        let diagnostics = check(&source);
+        assert_eq!(find(&diagnostics, "invalid color '#12'").line, Some(6));
+    }
+
+    #[test]
+    fn test_extends() {
+        let temp = TempDir::new();
+        let dir = temp.path();
+        let write = |name: &str, source: &str| {
+            temp.write(&format!("{}/{}", name, MANIFEST_FILE_NAME), source);
+        };
+        write("common", "device: akp03\npages_order: [main]\npages:\n  main: {}\n");
+        write("app", "extends: common\npages:\n  main:\n    0:\n      action:\n        navigate: main\n");
+        write("loop", "extends: loop\n");
+        write("orphan", "extends: missing\n");
+
+        let diagnostics = check_profiles(dir).unwrap();
+
+        assert!(!diagnostics.iter().any(|d| d.file.starts_with(dir.join("app"))));
+        let cycle = find(&diagnostics, "profile inheritance cycle: loop -> loop");
+        assert_eq!(cycle.line, Some(1));
+        find(&diagnostics, "extended profile 'missing' not found");
+        assert_eq!(diagnostics.len(), 2);
+    }
+
+    #[test]
+    fn test_invalid_match() {
+        let source = MANIFEST.replace(
+            "device: akp03",
+            "device: akp03\nmatch:\n  apps:\n    - com.jetbrains.*\n    - /[/\n  title: \"(\"",
+        );
+        let diagnostics = check(&source.replace("ctrl+foo", "ctrl+c"));
+        assert_eq!(find(&diagnostics, "invalid app pattern '/[/'").line, Some(9));
+        assert_eq!(find(&diagnostics, "invalid title pattern '('").line, Some(10));
+        assert!(!diagnostics.iter().any(|d| d.message.contains("com.jetbrains")));
+    }
+
+    #[test]
+    fn test_invalid_condition() {
+        let source = MANIFEST
+            .replace("ctrl+foo", "ctrl+c")
+            .replace("  extra: {}\n", "  extra:\n    when:\n      any:\n        - display: \"/(/\"\n");
+        let diagnostics = check(&source);
+        assert_eq!(find(&diagnostics, "invalid pattern '/(/'").line, Some(23));
+
+        let source = MANIFEST.replace("device: akp03", "device: akp03\nwhen:\n  time: 9-18");
+        let diagnostics = check(&source.replace("ctrl+foo", "ctrl+c"));
+        assert!(diagnostics.iter().any(|d| d.message.contains("invalid time range '9-18'")));
+    }
+
+    #[test]
+    fn test_syntax_error() {
+        let diagnostics = check("pages: [\n");
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].line.is_some());
+    }
+
+    #[test]
+    fn test_locator() {
+        let locator = Locator::new("a:\n  - b: 1 # comment\n    c: 2\n  - d\n\"e f\": 3\n");
+        assert_eq!(locator.locate(&["a", "[0]", "b"]), Some((2, 5)));
+        assert_eq!(locator.locate(&["a", "[0]", "c"]), Some((3, 5)));
+        assert_eq!(locator.locate(&["a", "[1]"]), Some((4, 3)));
+        assert_eq!(locator.locate(&["e f"]), Some((5, 1)));
+        assert_eq!(locator.locate(&["a", "[1]", "x"]), Some((4, 3)));
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::matcher::AppPattern;
+
+/// Condition is a set of requirements on the system state, all of them must hold.
+///
+/// Device and display names are patterns like the ones of `match.apps`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Condition {
+    /// AudioOutput is a pattern the default audio output device name must match.
+    #[serde(default)]
+    pub audio_output: Option<String>,
+    /// AudioInput is a pattern the default audio input device name must match.
+    #[serde(default)]
+    pub audio_input: Option<String>,
+    /// Display is a pattern one of the connected display names must match.
+    #[serde(default)]
+    pub display: Option<String>,
+    /// Time is a local time range, e.g. `09:00-18:00`.
+    #[serde(default)]
+    pub time: Option<TimeRange>,
+    /// Command is a shell command, zero exit status means the condition holds.
+    #[serde(default)]
+    pub command: Option<String>,
+    /// All are conditions that must all hold.
+    #[serde(default)]
+    pub all: Vec<Condition>,
+    /// Any are conditions of which at least one must hold.
+    #[serde(default)]
+    pub any: Vec<Condition>,
+    /// Not is a condition that must not hold.
+    #[serde(default)]
+    pub not: Option<Box<Condition>>,
+}
+
+/// ConditionContext is the system state conditions are evaluated against.
+#[derive(Debug, Clone, Default)]
+pub struct ConditionContext {
+    pub audio_output: String,
+    pub audio_input: String,
+    pub displays: Vec<String>,
+    /// Time is the local time in minutes since midnight.
+    pub time: u32,
+    /// Commands are the results of the commands returned by [`Condition::commands`].
+    pub commands: HashMap<String, bool>,
+}
+
+impl Condition {
+    pub fn evaluate(&self, context: &ConditionContext) -> bool {
+        let matches = |pattern: &Option<String>, value: &str| match pattern {
+            Some(pattern) => AppPattern::parse(pattern).is_ok_and(|p| p.matches(value)),
+            None => true,
+        };
+
+        matches(&self.audio_output, &context.audio_output)
+            && matches(&self.audio_input, &context.audio_input)
+            && (self.display.is_none()
+                || context.displays.iter().any(|display| matches(&self.display, display)))
+            && self.time.map_or(true, |time| time.contains(context.time))
+            && self.command.as_ref().map_or(true, |command| {
+                context.commands.get(command).copied().unwrap_or(false)
+            })
+            && self.all.iter().all(|condition| condition.evaluate(context))
+            && (self.any.is_empty() || self.any.iter().any(|condition| condition.evaluate(context)))
+            && self.not.as_ref().map_or(true, |condition| !condition.evaluate(context))
+    }
+
+    /// Returns the commands the condition depends on, including nested ones.
+    pub fn commands(&self) -> Vec<&str> {
+        let nested = self.all.iter().chain(self.any.iter()).chain(self.not.as_deref());
+        self.command
+            .as_deref()
+            .into_iter()
+            .chain(nested.flat_map(Condition::commands))
+            .collect()
+    }
+
+    /// Returns the time ranges the condition depends on, including nested ones.
+    pub fn time_ranges(&self) -> Vec<TimeRange> {
+        let nested = self.all.iter().chain(self.any.iter()).chain(self.not.as_deref());
+        self.time
+            .into_iter()
+            .chain(nested.flat_map(Condition::time_ranges))
+            .collect()
+    }
+}
+
+/// TimeRange is a range of the local time of day, it wraps around midnight if it ends before it starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TimeRange {
+    /// Start is the first minute of the range since midnight.
+    pub start: u32,
+    /// End is the minute since midnight the range ends before.
+    pub end: u32,
+}
+
+impl TimeRange {
+    pub fn contains(&self, minute: u32) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+/// Parses `HH:MM` into minutes since midnight.
+fn parse_time(time: &str) -> Option<u32> {
+    let (hours, minutes) = time.trim().split_once(':')?;
+    let hours: u32 = hours.parse().ok()?;
+    let minutes: u32 = minutes.parse().ok()?;
+    (hours <= 24 && minutes < 60 && hours * 60 + minutes <= 24 * 60).then_some(hours * 60 + minutes)
+}
+
+impl TryFrom<String> for TimeRange {
+    type Error = String;
+
+    fn try_from(range: String) -> Result<Self, Self::Error> {
+        let error = || format!("invalid time range '{}', expected HH:MM-HH:MM", range);
+        let (start, end) = range.split_once('-').ok_or_else(error)?;
+        Ok(TimeRange {
+            start: parse_time(start).ok_or_else(error)?,
+            end: parse_time(end).ok_or_else(error)?,
+        })
+    }
+}
+
+impl fmt::Display for TimeRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> ConditionContext {
+        ConditionContext {
+            audio_output: "AirPods Pro".to_string(),
+            audio_input: "MacBook Pro Microphone".to_string(),
+            displays: vec!["Built-in Retina Display".to_string(), "DELL U2720Q".to_string()],
+            time: 10 * 60,
+            commands: HashMap::from([("pgrep zoom".to_string(), true)]),
+        }
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let condition = |source: &str| serde_yaml::from_str::<Condition>(source).unwrap();
+        let context = context();
+
+        assert!(condition("{}").evaluate(&context));
+        assert!(condition("audio_output: AirPods*").evaluate(&context));
+        assert!(!condition("audio_output: AirPods*\naudio_input: AirPods*").evaluate(&context));
+        assert!(condition("display: DELL*").evaluate(&context));
+        assert!(condition("time: 09:00-18:00\ncommand: pgrep zoom").evaluate(&context));
+        assert!(!condition("command: pgrep slack").evaluate(&context));
+        assert!(condition("any:\n  - display: LG*\n  - time: 22:00-11:00").evaluate(&context));
+        assert!(!condition("not:\n  display: /U27/").evaluate(&context));
+
+        let nested = condition("command: a\nany:\n  - command: b\nnot:\n  command: c");
+        assert_eq!(nested.commands(), vec!["a", "b", "c"]);
+        let nested = condition("time: 09:00-18:00\nall:\n  - time: 22:00-06:00");
+        let ranges: Vec<String> = nested.time_ranges().iter().map(ToString::to_string).collect();
+        assert_eq!(ranges, vec!["09:00-18:00", "22:00-06:00"]);
+    }
+
+    #[test]
+    fn test_time_range() {
+        let range = TimeRange::try_from("22:30-06:00".to_string()).unwrap();
+        assert!(range.contains(23 * 60));
+        assert!(range.contains(5 * 60));
+        assert!(!range.contains(6 * 60));
+        assert_eq!(range.to_string(), "22:30-06:00");
+
+        assert!(TimeRange::try_from("9-18".to_string()).is_err());
+        assert!(TimeRange::try_from("09:00-25:00".to_string()).is_err());
+    }
+}
@@ -0,0 +1,155 @@
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::Duration;
+
+use image::codecs::gif::GifDecoder;
+use image::{AnimationDecoder, DynamicImage};
+
+/// Frames shorter than this are shown for [`DEFAULT_FRAME_DELAY`], as browsers do.
+const MIN_FRAME_DELAY: Duration = Duration::from_millis(20);
+const DEFAULT_FRAME_DELAY: Duration = Duration::from_millis(100);
+
+/// Frame is a single image of an animation.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub image: DynamicImage,
+    pub delay: Duration,
+}
+
+/// Animation is a looped sequence of frames.
+#[derive(Debug)]
+pub struct Animation {
+    frames: Vec<Frame>,
+    duration: Duration,
+}
+
+impl Animation {
+    /// Creates an animation, `frames` must not be empty.
+    pub fn new(frames: Vec<Frame>) -> Self {
+        let frames: Vec<Frame> = frames
+            .into_iter()
+            .map(|frame| Frame {
+                delay: if frame.delay < MIN_FRAME_DELAY {
+                    DEFAULT_FRAME_DELAY
+                } else {
+                    frame.delay
+                },
+                ..frame
+            })
+            .collect();
+        let duration = frames.iter().map(|frame| frame.delay).sum();
+        Self { frames, duration }
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Returns the index of the frame shown after `elapsed` and the time it stays shown.
+    pub fn frame_at(&self, elapsed: Duration) -> (usize, Duration) {
+        let position = Duration::from_nanos((elapsed.as_nanos() % self.duration.as_nanos()) as u64);
+        let mut end = Duration::ZERO;
+        for (index, frame) in self.frames.iter().enumerate() {
+            end += frame.delay;
+            if position < end {
+                return (index, end - position);
+            }
+        }
+        (0, self.frames[0].delay)
+    }
+}
+
+/// KeyImage is an image shown on a key.
+#[derive(Debug, Clone)]
+pub enum KeyImage {
+    Static(DynamicImage),
+    Animated(Arc<Animation>),
+}
+
+impl KeyImage {
+    /// Returns the image shown when the key is drawn.
+    pub fn first_frame(&self) -> &DynamicImage {
+        match self {
+            KeyImage::Static(image) => image,
+            KeyImage::Animated(animation) => &animation.frames[0].image,
+        }
+    }
+}
+
+impl PartialEq for KeyImage {
+    /// Animations are compared by identity, as comparing every frame is too expensive.
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (KeyImage::Static(a), KeyImage::Static(b)) => a == b,
+            (KeyImage::Animated(a), KeyImage::Animated(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+pub(crate) fn is_gif(data: &[u8]) -> bool {
+    data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a")
+}
+
+/// Decodes every frame of a GIF image.
+pub(crate) fn decode_gif_frames(data: &[u8]) -> Result<Vec<Frame>, image::ImageError> {
+    let decoder = GifDecoder::new(Cursor::new(data))?;
+    let frames = decoder.into_frames().collect_frames()?;
+    Ok(frames
+        .into_iter()
+        .map(|frame| {
+            let (numerator, denominator) = frame.delay().numer_denom_ms();
+            let delay = Duration::from_millis((numerator / denominator.max(1)) as u64);
+            Frame {
+                image: DynamicImage::ImageRgba8(frame.into_buffer()),
+                delay,
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::codecs::gif::{GifEncoder, Repeat};
+    use image::{Delay, Rgba, RgbaImage};
+
+    fn frame(color: u8, delay_ms: u64) -> Frame {
+        Frame {
+            image: DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba([color, 0, 0, 255]))),
+            delay: Duration::from_millis(delay_ms),
+        }
+    }
+
+    #[test]
+    fn test_frame_at() {
+        let animation = Animation::new(vec![frame(0, 100), frame(1, 200), frame(2, 0)]);
+
+        assert_eq!(animation.frame_at(Duration::ZERO), (0, Duration::from_millis(100)));
+        assert_eq!(animation.frame_at(Duration::from_millis(150)), (1, Duration::from_millis(150)));
+        // The zero delay is replaced with the default one.
+        assert_eq!(animation.frame_at(Duration::from_millis(350)), (2, Duration::from_millis(50)));
+        assert_eq!(animation.frame_at(Duration::from_millis(410)), (0, Duration::from_millis(90)));
+    }
+
+    #[test]
+    fn test_decode_gif_frames() {
+        let mut data = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut data);
+            encoder.set_repeat(Repeat::Infinite).unwrap();
+            for color in [0, 255] {
+                let image = RgbaImage::from_pixel(4, 4, Rgba([color, 0, 0, 255]));
+                let delay = Delay::from_numer_denom_ms(50, 1);
+                encoder.encode_frame(image::Frame::from_parts(image, 0, 0, delay)).unwrap();
+            }
+        }
+
+        assert!(is_gif(&data));
+        let frames = decode_gif_frames(&data).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].delay, Duration::from_millis(50));
+        assert_eq!(frames[1].image.to_rgba8().get_pixel(0, 0)[0], 255);
+    }
+}
@@ -0,0 +1,63 @@
+use image::Rgba;
+
+/// Parses a `#rgb`, `#rrggbb`, `#rrggbbaa` or named colour.
+pub fn parse_color(value: &str) -> Option<Rgba<u8>> {
+    let value = value.trim();
+    let Some(hex) = value.strip_prefix('#') else {
+        return named_color(&value.to_lowercase());
+    };
+    if !hex.is_ascii() {
+        return None;
+    }
+
+    let channel = |index: usize, len: usize| {
+        let digits = &hex[index * len..(index + 1) * len];
+        let channel = u8::from_str_radix(digits, 16).ok()?;
+        Some(if len == 1 { channel * 17 } else { channel })
+    };
+    match hex.len() {
+        3 => Some(Rgba([channel(0, 1)?, channel(1, 1)?, channel(2, 1)?, 255])),
+        6 => Some(Rgba([channel(0, 2)?, channel(1, 2)?, channel(2, 2)?, 255])),
+        8 => Some(Rgba([
+            channel(0, 2)?,
+            channel(1, 2)?,
+            channel(2, 2)?,
+            channel(3, 2)?,
+        ])),
+        _ => None,
+    }
+}
+
+fn named_color(name: &str) -> Option<Rgba<u8>> {
+    let rgb = match name {
+        "black" => [0, 0, 0],
+        "white" => [255, 255, 255],
+        "gray" | "grey" => [128, 128, 128],
+        "red" => [255, 59, 48],
+        "orange" => [255, 149, 0],
+        "yellow" => [255, 204, 0],
+        "green" => [52, 199, 89],
+        "blue" => [0, 122, 255],
+        "purple" => [175, 82, 222],
+        "pink" => [255, 45, 85],
+        "transparent" => return Some(Rgba([0, 0, 0, 0])),
+        _ => return None,
+    };
+    Some(Rgba([rgb[0], rgb[1], rgb[2], 255]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("#fff"), Some(Rgba([255, 255, 255, 255])));
+        assert_eq!(parse_color("#1e1e2e"), Some(Rgba([30, 30, 46, 255])));
+        assert_eq!(parse_color("#ff000080"), Some(Rgba([255, 0, 0, 128])));
+        assert_eq!(parse_color("White"), Some(Rgba([255, 255, 255, 255])));
+        assert_eq!(parse_color("#12345"), None);
+        assert_eq!(parse_color("#zzz"), None);
+        assert_eq!(parse_color("icons/background.png"), None);
+    }
+}
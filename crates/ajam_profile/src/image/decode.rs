@@ -0,0 +1,188 @@
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use image::{imageops, DynamicImage, ImageFormat, Rgba, RgbaImage};
+use resvg::{tiny_skia, usvg};
+
+use super::animation::{decode_gif_frames, is_gif, Frame};
+use super::normalize::ImageFit;
+use super::ImageError;
+
+/// Returns true if the image format of the path is supported.
+pub fn is_supported_image(path: &Path) -> bool {
+    has_svg_extension(path) || ImageFormat::from_path(path).is_ok_and(|format| format.can_read())
+}
+
+/// Decodes an image file.
+///
+/// The format is sniffed from the content, the extension is used when the content is ambiguous.
+/// SVG images are rasterised at `size`, scaled according to `fit`.
+pub(crate) fn decode(
+    path: &Path,
+    size: (u32, u32),
+    fit: ImageFit,
+) -> Result<DynamicImage, ImageError> {
+    let data = read(path)?;
+    decode_data(path, &data, size, fit)
+}
+
+/// Decodes every frame of an image file, a still image has a single frame.
+pub(crate) fn decode_frames(
+    path: &Path,
+    size: (u32, u32),
+    fit: ImageFit,
+) -> Result<Vec<Frame>, ImageError> {
+    let data = read(path)?;
+    if is_gif(&data) {
+        let frames = decode_gif_frames(&data)
+            .map_err(|e| ImageError::LoadError(path.display().to_string(), e))?;
+        if frames.len() > 1 {
+            return Ok(frames);
+        }
+    }
+
+    let image = decode_data(path, &data, size, fit)?;
+    Ok(vec![Frame {
+        image,
+        delay: Duration::ZERO,
+    }])
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, ImageError> {
+    fs::read(path).map_err(|e| {
+        ImageError::LoadError(path.display().to_string(), image::ImageError::IoError(e))
+    })
+}
+
+fn decode_data(
+    path: &Path,
+    data: &[u8],
+    size: (u32, u32),
+    fit: ImageFit,
+) -> Result<DynamicImage, ImageError> {
+    let load_error = |e| ImageError::LoadError(path.display().to_string(), e);
+    if has_svg_extension(path) || is_svg(data) {
+        return rasterize_svg(path, data, size, fit);
+    }
+
+    match image::load_from_memory(data) {
+        Ok(image) => Ok(image),
+        Err(e) => match ImageFormat::from_path(path) {
+            Ok(format) => image::load_from_memory_with_format(data, format).map_err(load_error),
+            Err(_) => Err(load_error(e)),
+        },
+    }
+}
+
+/// Composites the image onto the background, dropping the alpha channel.
+pub(crate) fn flatten(image: DynamicImage, background: Rgba<u8>) -> DynamicImage {
+    if !image.color().has_alpha() && background[3] == 255 {
+        return image;
+    }
+
+    // A translucent background is shown over the black screen.
+    let alpha = background[3] as u16;
+    let channel = |index: usize| (background[index] as u16 * alpha / 255) as u8;
+    let opaque = Rgba([channel(0), channel(1), channel(2), 255]);
+    let mut canvas = RgbaImage::from_pixel(image.width(), image.height(), opaque);
+    imageops::overlay(&mut canvas, &image.to_rgba8(), 0, 0);
+    DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(canvas).to_rgb8())
+}
+
+fn has_svg_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("svg"))
+}
+
+fn is_svg(data: &[u8]) -> bool {
+    let head = &data[..data.len().min(256)];
+    let head = String::from_utf8_lossy(head);
+    let head = head.trim_start_matches('\u{feff}').trim_start();
+    head.starts_with("<svg") || (head.starts_with("<?xml") && head.contains("<svg"))
+}
+
+fn rasterize_svg(
+    path: &Path,
+    data: &[u8],
+    size: (u32, u32),
+    fit: ImageFit,
+) -> Result<DynamicImage, ImageError> {
+    let svg_error = |message: String| ImageError::SvgError(path.display().to_string(), message);
+    let tree = usvg::Tree::from_data(data, &usvg::Options::default())
+        .map_err(|e| svg_error(e.to_string()))?;
+
+    let svg_size = tree.size();
+    let (scale_x, scale_y) = fit.scale((svg_size.width(), svg_size.height()), size);
+    let width = ((svg_size.width() * scale_x).round() as u32).max(1);
+    let height = ((svg_size.height() * scale_y).round() as u32).max(1);
+    let mut pixmap = tiny_skia::Pixmap::new(width, height)
+        .ok_or_else(|| svg_error(format!("invalid size {}x{}", width, height)))?;
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale_x, scale_y),
+        &mut pixmap.as_mut(),
+    );
+
+    // Pixmap stores premultiplied alpha.
+    let pixels = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let color = pixel.demultiply();
+            [color.red(), color.green(), color.blue(), color.alpha()]
+        })
+        .collect();
+    let image = RgbaImage::from_raw(width, height, pixels)
+        .ok_or_else(|| svg_error("invalid pixmap".to_string()))?;
+    Ok(DynamicImage::ImageRgba8(image))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TempDir;
+
+    use super::*;
+
+    const SVG: &str = r##"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+<rect width="10" height="5" fill="#ff0000"/>
+</svg>"##;
+
+    #[test]
+    fn test_svg_is_rasterized_and_flattened() {
+        let dir = TempDir::new();
+        // No extension, the format is sniffed from the content.
+        let path = dir.write("icon", SVG);
+
+        let image = decode(&path, (60, 60), ImageFit::Fit).unwrap();
+        assert_eq!((image.width(), image.height()), (60, 60));
+
+        let image = flatten(image, Rgba([0, 0, 255, 255])).to_rgb8();
+        assert_eq!(image.get_pixel(30, 10).0, [255, 0, 0]);
+        assert_eq!(image.get_pixel(30, 50).0, [0, 0, 255]);
+    }
+
+    #[test]
+    fn test_png_with_transparency() {
+        let dir = TempDir::new();
+        let path = dir.path().join("icon.png");
+        let mut source = RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, 0]));
+        source.put_pixel(0, 0, Rgba([255, 255, 255, 255]));
+        source.save(&path).unwrap();
+
+        let image = flatten(decode(&path, (60, 60), ImageFit::Fit).unwrap(), Rgba([0, 128, 0, 255]));
+        assert!(!image.color().has_alpha());
+        let image = image.to_rgb8();
+        assert_eq!(image.get_pixel(0, 0).0, [255, 255, 255]);
+        assert_eq!(image.get_pixel(3, 3).0, [0, 128, 0]);
+    }
+
+    #[test]
+    fn test_supported_formats() {
+        for name in ["a.png", "a.JPG", "a.webp", "a.gif", "a.bmp", "a.svg"] {
+            assert!(is_supported_image(Path::new(name)), "{}", name);
+        }
+        assert!(!is_supported_image(Path::new("a.txt")));
+    }
+}
@@ -0,0 +1,239 @@
+use std::path::Path;
+
+use ab_glyph::{point, Font, FontVec, PxScale, ScaleFont};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, Rgba, RgbaImage};
+use serde::Deserialize;
+
+use super::color::parse_color;
+use super::decode::decode;
+use super::normalize::ImageFit;
+use super::ImageError;
+
+pub const DEFAULT_FONT: &str = "/System/Library/Fonts/Helvetica.ttc";
+pub const DEFAULT_FONT_SIZE: f32 = 14.0;
+pub const DEFAULT_TEXT_COLOR: &str = "#ffffff";
+pub const DEFAULT_BACKGROUND: &str = "#000000";
+
+const PADDING: u32 = 4;
+const ICON_SCALE: f32 = 0.6;
+
+/// Align is the vertical position of a label.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Align {
+    Top,
+    #[default]
+    Center,
+    Bottom,
+}
+
+/// GeneratedImage is a button image composed at runtime.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GeneratedImage {
+    /// Background is a colour or a path to an image file.
+    #[serde(default)]
+    pub background: Option<String>,
+    /// Icon is a path to an image file drawn in the middle of the key.
+    #[serde(default)]
+    pub icon: Option<String>,
+    /// Label is the text drawn over the background and icon.
+    #[serde(default)]
+    pub label: Option<String>,
+    /// Font is a path to a TrueType or OpenType font.
+    #[serde(default)]
+    pub font: Option<String>,
+    #[serde(default = "default_font_size")]
+    pub font_size: f32,
+    #[serde(default = "default_text_color")]
+    pub color: String,
+    #[serde(default)]
+    pub align: Align,
+}
+
+fn default_font_size() -> f32 {
+    DEFAULT_FONT_SIZE
+}
+
+fn default_text_color() -> String {
+    DEFAULT_TEXT_COLOR.to_string()
+}
+
+impl GeneratedImage {
+    /// Returns the background colour, or `None` if the background is an image.
+    pub fn background_color(&self) -> Option<Rgba<u8>> {
+        parse_color(self.background.as_deref().unwrap_or(DEFAULT_BACKGROUND))
+    }
+
+    /// Returns a key identifying the image with its parameters for the cache.
+    ///
+    /// The key is nested in the profile path, so it is dropped along with the profile images.
+    pub(crate) fn cache_key(&self, profile_path: &Path, size: (u32, u32)) -> String {
+        let name = format!("generated:{}x{}:{:?}", size.0, size.1, self);
+        profile_path.join(name).display().to_string()
+    }
+
+    pub(crate) fn render(
+        &self,
+        profile_path: &Path,
+        size: (u32, u32),
+    ) -> Result<DynamicImage, ImageError> {
+        let (width, height) = size;
+        let mut canvas = match (self.background_color(), &self.background) {
+            (Some(color), _) => RgbaImage::from_pixel(width, height, color),
+            (None, Some(path)) => decode(&profile_path.join(path), size, ImageFit::Fill)?
+                .resize_to_fill(width, height, FilterType::Triangle)
+                .to_rgba8(),
+            (None, None) => unreachable!("default background is a colour"),
+        };
+
+        if let Some(icon) = &self.icon {
+            let max_width = (width as f32 * ICON_SCALE) as u32;
+            let max_height = (height as f32 * ICON_SCALE) as u32;
+            let icon = decode(&profile_path.join(icon), size, ImageFit::Fit)?
+                .resize(max_width, max_height, FilterType::Triangle)
+                .to_rgba8();
+            let x = (width - icon.width()) / 2;
+            let y = (height - icon.height()) / 2;
+            imageops::overlay(&mut canvas, &icon, x as i64, y as i64);
+        }
+
+        if let Some(label) = &self.label {
+            let color = parse_color(&self.color)
+                .ok_or_else(|| ImageError::InvalidColor(self.color.clone()))?;
+            let font_path = self.font.as_deref().unwrap_or(DEFAULT_FONT);
+            let font = load_font(&profile_path.join(font_path))?;
+            draw_label(&mut canvas, &font, self.font_size, color, label, self.align);
+        }
+
+        Ok(DynamicImage::ImageRgba8(canvas))
+    }
+}
+
+fn load_font(path: &Path) -> Result<FontVec, ImageError> {
+    let font_error = |message: String| ImageError::FontError(path.display().to_string(), message);
+    let data = std::fs::read(path).map_err(|e| font_error(e.to_string()))?;
+    FontVec::try_from_vec_and_index(data, 0).map_err(|e| font_error(e.to_string()))
+}
+
+fn line_width<F: Font>(font: &ab_glyph::PxScaleFont<F>, line: &str) -> f32 {
+    let mut width = 0.0;
+    let mut previous = None;
+    for ch in line.chars() {
+        let glyph_id = font.glyph_id(ch);
+        if let Some(previous) = previous {
+            width += font.kern(previous, glyph_id);
+        }
+        width += font.h_advance(glyph_id);
+        previous = Some(glyph_id);
+    }
+    width
+}
+
+fn draw_label(
+    canvas: &mut RgbaImage,
+    font: &FontVec,
+    font_size: f32,
+    color: Rgba<u8>,
+    label: &str,
+    align: Align,
+) {
+    let font = font.as_scaled(PxScale::from(font_size));
+    let lines: Vec<&str> = label.lines().collect();
+    let line_height = font.height() + font.line_gap();
+    let text_height = line_height * lines.len() as f32 - font.line_gap();
+    let (width, height) = (canvas.width() as f32, canvas.height() as f32);
+
+    let top = match align {
+        Align::Top => PADDING as f32,
+        Align::Center => (height - text_height) / 2.0,
+        Align::Bottom => height - text_height - PADDING as f32,
+    };
+
+    for (index, line) in lines.iter().enumerate() {
+        let mut x = (width - line_width(&font, line)) / 2.0;
+        let baseline = top + line_height * index as f32 + font.ascent();
+        let mut previous = None;
+
+        for ch in line.chars() {
+            let glyph_id = font.glyph_id(ch);
+            if let Some(previous) = previous {
+                x += font.kern(previous, glyph_id);
+            }
+            let glyph = glyph_id.with_scale_and_position(font.scale(), point(x, baseline));
+            x += font.h_advance(glyph_id);
+            previous = Some(glyph_id);
+
+            let Some(outlined) = font.outline_glyph(glyph) else {
+                continue;
+            };
+            let bounds = outlined.px_bounds();
+            outlined.draw(|gx, gy, coverage| {
+                let px = bounds.min.x as i32 + gx as i32;
+                let py = bounds.min.y as i32 + gy as i32;
+                if px < 0 || py < 0 || px >= canvas.width() as i32 || py >= canvas.height() as i32 {
+                    return;
+                }
+                let pixel = canvas.get_pixel_mut(px as u32, py as u32);
+                let alpha = coverage * color[3] as f32 / 255.0;
+                for channel in 0..3 {
+                    pixel[channel] = (color[channel] as f32 * alpha
+                        + pixel[channel] as f32 * (1.0 - alpha)) as u8;
+                }
+                pixel[3] = pixel[3].max((alpha * 255.0) as u8);
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(yaml: &str) -> GeneratedImage {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_background_color() {
+        let image = spec("background: \"#ff0000\"")
+            .render(Path::new("/"), (60, 60))
+            .unwrap()
+            .to_rgba8();
+
+        assert_eq!(image.dimensions(), (60, 60));
+        assert_eq!(image.get_pixel(30, 30), &Rgba([255, 0, 0, 255]));
+    }
+
+    /// Returns the first and last rows the label of the spec is drawn on.
+    fn label_rows(yaml: &str) -> (u32, u32) {
+        let image = spec(yaml).render(Path::new("/"), (60, 60)).unwrap().to_rgba8();
+        assert_eq!(image.get_pixel(0, 0), &Rgba([0, 0, 0, 255]));
+        let rows: Vec<u32> = (0..60)
+            .filter(|&y| (0..60).any(|x| image.get_pixel(x, y)[0] > 128))
+            .collect();
+        assert!(!rows.is_empty(), "no label drawn for {:?}", yaml);
+        (rows[0], rows[rows.len() - 1])
+    }
+
+    #[test]
+    fn test_label() {
+        // The default font is used when none is set.
+        let (top, bottom) = label_rows("label: Build\nfont_size: 20");
+        assert!(top > 10 && bottom < 50, "label is not centred: {}-{}", top, bottom);
+
+        let (top_aligned, _) = label_rows("label: Build\nfont_size: 20\nalign: top");
+        let (_, bottom_aligned) = label_rows("label: Build\nfont_size: 20\nalign: bottom");
+        assert!(top_aligned < top);
+        assert!(bottom_aligned > bottom);
+    }
+
+    #[test]
+    fn test_invalid_spec() {
+        assert!(serde_yaml::from_str::<GeneratedImage>("lable: typo").is_err());
+
+        let result = spec("label: x\ncolor: nope").render(Path::new("/"), (60, 60));
+        assert!(matches!(result, Err(ImageError::InvalidColor(_))));
+    }
+}
@@ -0,0 +1,318 @@
+mod animation;
+mod color;
+mod decode;
+mod generate;
+mod normalize;
+
+use image::{DynamicImage, Rgba};
+use lru::LruCache;
+use std::{collections::HashMap, num::NonZeroUsize, path::{Path, PathBuf}, sync::Arc};
+use thiserror::Error;
+
+use serde::Deserialize;
+
+pub use animation::{Animation, Frame, KeyImage};
+pub use color::parse_color;
+pub use decode::is_supported_image;
+pub use generate::{Align, GeneratedImage, DEFAULT_FONT};
+pub use normalize::ImageFit;
+
+/// ButtonImage is an image for a screen button.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ButtonImage {
+    /// Source is a path to an static image file.
+    /// PNG, JPEG, WebP, GIF, BMP and SVG images are supported, animated GIFs are played.
+    Source { src: String },
+    /// AudioInput is a map of audio input device name to a path to an image file.
+    /// Must include "default" key.
+    AudioInput {
+        audio_input: HashMap<String, String>,
+    },
+    /// AudioOutput is a map of audio output device name to a path to an image file.
+    /// Must include "default" key.
+    AudioOutput {
+        audio_output: HashMap<String, String>,
+    },
+    /// Command is a map of a shell command output to a path to an image file.
+    /// The command runs every `interval_ms`, its trimmed stdout is the key.
+    /// Must include "default" key.
+    Command {
+        command: String,
+        #[serde(default = "default_command_interval")]
+        interval_ms: u64,
+        images: HashMap<String, String>,
+    },
+    /// Generated is an image composed from a background, an icon and a label.
+    Generated(Box<GeneratedImage>),
+}
+
+pub const DEFAULT_COMMAND_INTERVAL_MS: u64 = 5000;
+
+fn default_command_interval() -> u64 {
+    DEFAULT_COMMAND_INTERVAL_MS
+}
+
+/// Returns the path of the image for the key, falling back to the default image.
+pub fn image_map_path<'a>(images: &'a HashMap<String, String>, key: &str) -> Option<&'a String> {
+    images.get(key).or_else(|| images.get(DEFAULT_IMAGE))
+}
+
+/// ImageLoader is a trait for loading images.
+pub trait ImageLoader {
+    fn open<P: AsRef<Path>>(&mut self, path: P) -> Result<KeyImage, ImageError>;
+    fn open_from_image_map(
+        &mut self,
+        images: &HashMap<String, String>,
+        key: &str,
+    ) -> Result<KeyImage, ImageError>;
+    fn generate(&mut self, spec: &GeneratedImage) -> Result<KeyImage, ImageError>;
+}
+
+/// ImageCache is a cache for images.
+#[derive(Debug, Clone)]
+pub struct ImageCache(LruCache<String, KeyImage>);
+
+impl ImageCache {
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self(LruCache::new(capacity))
+    }
+
+    pub fn get(&mut self, key: &str) -> Option<&KeyImage> {
+        self.0.get(key)
+    }
+
+    pub fn put(&mut self, key: String, image: KeyImage) {
+        self.0.put(key, image);
+    }
+
+    /// Removes every image loaded from within the directory.
+    pub fn invalidate_dir(&mut self, dir: &Path) {
+        let stale: Vec<String> = self
+            .0
+            .iter()
+            .filter(|(key, _)| Path::new(key.as_str()).starts_with(dir))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in stale {
+            self.0.pop(&key);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// ButtonImageLoader is a loader for button images.
+#[derive(Debug)]
+pub struct ButtonImageLoader<'a> {
+    cache: &'a mut ImageCache,
+    profile_path: PathBuf,
+    origin: Option<PathBuf>,
+    size: (u32, u32),
+    background: Rgba<u8>,
+    fit: ImageFit,
+}
+
+/// ImageError is an error for loading images.
+#[derive(Error, Debug)]
+pub enum ImageError {
+    #[error("image error: {0}")]
+    LoadError(String, image::ImageError),
+
+    #[error("image not found: {0}")]
+    ImageNotFound(String),
+
+    #[error("default image not found at {0}")]
+    DefaultImageNotFound(String),
+
+    #[error("font error in {0}: {1}")]
+    FontError(String, String),
+
+    #[error("invalid color: {0}")]
+    InvalidColor(String),
+
+    #[error("svg error in {0}: {1}")]
+    SvgError(String, String),
+}
+
+/// ButtonImageLoader is a loader for button images.
+impl<'a> ButtonImageLoader<'a> {
+    /// Creates a loader for a profile, generated images are rendered at `size`.
+    pub fn new(cache: &'a mut ImageCache, profile_path: PathBuf, size: (u32, u32)) -> Self {
+        Self {
+            cache,
+            profile_path,
+            origin: None,
+            size,
+            background: Rgba([0, 0, 0, 255]),
+            fit: ImageFit::default(),
+        }
+    }
+
+    /// Sets how images are scaled to the key size.
+    pub fn with_fit(mut self, fit: ImageFit) -> Self {
+        self.fit = fit;
+        self
+    }
+
+    /// Changes how the next images are scaled, e.g. for a button overriding the profile.
+    pub fn set_fit(&mut self, fit: ImageFit) {
+        self.fit = fit;
+    }
+
+    /// Changes where the next images are loaded from, `None` is the profile directory.
+    ///
+    /// Buttons inherited from an extended profile load images from its directory.
+    pub fn set_origin(&mut self, origin: Option<&Path>) {
+        self.origin = origin.map(Path::to_path_buf);
+    }
+
+    fn dir(&self) -> &Path {
+        self.origin.as_deref().unwrap_or(&self.profile_path)
+    }
+
+    /// Brings a decoded image to the key size and drops its transparency.
+    fn finish(&self, image: DynamicImage) -> DynamicImage {
+        let image = normalize::normalize(image, self.size, self.fit);
+        decode::flatten(image, self.background)
+    }
+
+    /// Sets the colour transparent images are composited onto.
+    pub fn with_background(mut self, background: Rgba<u8>) -> Self {
+        self.background = background;
+        self
+    }
+}
+
+const DEFAULT_IMAGE: &str = "default";
+
+/// ImageLoader is a trait for loading images.
+impl ImageLoader for ButtonImageLoader<'_> {
+    /// Open an image from a path.
+    fn open<P: AsRef<Path>>(&mut self, path: P) -> Result<KeyImage, ImageError> {
+        let image_path = path.as_ref();
+        let file_path = self.dir().join(image_path);
+        // The normalised image is cached, so the key depends on the key size, fit mode
+        // and the background it is flattened onto.
+        let cache_key = format!(
+            "{}#{}x{}#{:?}#{:?}",
+            file_path.display(),
+            self.size.0,
+            self.size.1,
+            self.fit,
+            self.background.0
+        );
+        if let Some(image) = self.cache.get(&cache_key) {
+            return Ok(image.clone());
+        };
+
+        let mut frames = decode::decode_frames(&file_path, self.size, self.fit)?;
+        for frame in frames.iter_mut() {
+            frame.image = self.finish(std::mem::take(&mut frame.image));
+        }
+        let image = match frames.len() {
+            1 => KeyImage::Static(frames.remove(0).image),
+            _ => KeyImage::Animated(Arc::new(Animation::new(frames))),
+        };
+        self.cache.put(cache_key, image.clone());
+        Ok(image)
+    }
+
+    /// Open an image from a map of images.
+    ///
+    /// If the key is not found, the default image is used.
+    fn open_from_image_map(
+        &mut self,
+        images: &HashMap<String, String>,
+        key: &str,
+    ) -> Result<KeyImage, ImageError> {
+        let Some(image) = images.get(key) else {
+            let default = images
+                .get(DEFAULT_IMAGE)
+                .ok_or(ImageError::DefaultImageNotFound(key.to_string()))?;
+            return self.open(default);
+        };
+
+        self.open(image)
+    }
+
+    /// Render a generated image at the key size.
+    fn generate(&mut self, spec: &GeneratedImage) -> Result<KeyImage, ImageError> {
+        let dir = self.dir().to_path_buf();
+        let cache_key = format!("{}#{:?}", spec.cache_key(&dir, self.size), self.background.0);
+        if let Some(image) = self.cache.get(&cache_key) {
+            return Ok(image.clone());
+        }
+
+        let image = KeyImage::Static(self.finish(spec.render(&dir, self.size)?));
+        self.cache.put(cache_key, image.clone());
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_command_image() {
+        let image: ButtonImage = serde_yaml::from_str(
+            r#"
+command: "git status --porcelain | wc -l"
+images:
+  "0": clean.png
+  default: dirty.png
+"#,
+        )
+        .unwrap();
+
+        let ButtonImage::Command { interval_ms, images, .. } = image else {
+            panic!("expected a command image");
+        };
+        assert_eq!(interval_ms, DEFAULT_COMMAND_INTERVAL_MS);
+        assert_eq!(image_map_path(&images, "0").unwrap(), "clean.png");
+        assert_eq!(image_map_path(&images, "3").unwrap(), "dirty.png");
+    }
+
+    #[test]
+    fn test_generated_image() {
+        let image: ButtonImage =
+            serde_yaml::from_str("label: Build\nbackground: \"#1e1e2e\"\nalign: bottom").unwrap();
+
+        let ButtonImage::Generated(spec) = image else {
+            panic!("expected a generated image");
+        };
+        assert_eq!(spec.label.as_deref(), Some("Build"));
+        assert_eq!(spec.align, Align::Bottom);
+        assert!(spec.background_color().is_some());
+
+        let image: ButtonImage = serde_yaml::from_str("src: a.png").unwrap();
+        assert!(matches!(image, ButtonImage::Source { .. }));
+    }
+
+    #[test]
+    fn test_cache_key_includes_background() {
+        let dir = TempDir::new();
+        image::RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, 0]))
+            .save(dir.path().join("icon.png"))
+            .unwrap();
+        let mut cache = ImageCache::new(NonZeroUsize::new(8).unwrap());
+
+        let mut open = |background: Rgba<u8>| {
+            let mut loader = ButtonImageLoader::new(&mut cache, dir.path().to_path_buf(), (60, 60))
+                .with_background(background);
+            let image = loader.open("icon.png").unwrap();
+            image.first_frame().to_rgb8().get_pixel(0, 0).0
+        };
+        assert_eq!(open(Rgba([255, 0, 0, 255])), [255, 0, 0]);
+        assert_eq!(open(Rgba([0, 0, 255, 255])), [0, 0, 255]);
+    }
+}
@@ -0,0 +1,86 @@
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, Rgba, RgbaImage};
+use serde::Deserialize;
+
+/// ImageFit is how an image is scaled to the key size.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFit {
+    /// Fit scales the image to fit the key, the rest is filled with the background.
+    #[default]
+    Fit,
+    /// Fill scales the image to cover the key, the overflow is cropped.
+    Fill,
+    /// Stretch scales the image to the key size, ignoring its aspect ratio.
+    Stretch,
+}
+
+impl ImageFit {
+    /// Returns the horizontal and vertical scale factors to bring `from` to `to`.
+    pub(crate) fn scale(&self, from: (f32, f32), to: (u32, u32)) -> (f32, f32) {
+        let scale_x = to.0 as f32 / from.0;
+        let scale_y = to.1 as f32 / from.1;
+        match self {
+            ImageFit::Fit => (scale_x.min(scale_y), scale_x.min(scale_y)),
+            ImageFit::Fill => (scale_x.max(scale_y), scale_x.max(scale_y)),
+            ImageFit::Stretch => (scale_x, scale_y),
+        }
+    }
+}
+
+/// Brings the image to the key size.
+///
+/// Rotation and mirroring of the key are applied by the SDK when the image is sent,
+/// so the image is kept in its upright orientation here.
+pub(crate) fn normalize(image: DynamicImage, size: (u32, u32), fit: ImageFit) -> DynamicImage {
+    let (width, height) = size;
+    if image.width() == width && image.height() == height {
+        return image;
+    }
+
+    match fit {
+        ImageFit::Fit => {
+            let image = image.resize(width, height, FilterType::Triangle);
+            let mut canvas = RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 0]));
+            let x = (width - image.width()) / 2;
+            let y = (height - image.height()) / 2;
+            imageops::overlay(&mut canvas, &image.to_rgba8(), x as i64, y as i64);
+            DynamicImage::ImageRgba8(canvas)
+        }
+        ImageFit::Fill => image.resize_to_fill(width, height, FilterType::Triangle),
+        ImageFit::Stretch => image.resize_exact(width, height, FilterType::Triangle),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wide_image() -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(120, 60, Rgba([255, 0, 0, 255])))
+    }
+
+    #[test]
+    fn test_fit() {
+        let image = normalize(wide_image(), (60, 60), ImageFit::Fit).to_rgba8();
+        assert_eq!(image.dimensions(), (60, 60));
+        assert_eq!(image.get_pixel(30, 30), &Rgba([255, 0, 0, 255]));
+        assert_eq!(image.get_pixel(30, 2)[3], 0);
+    }
+
+    #[test]
+    fn test_fill_and_stretch() {
+        for fit in [ImageFit::Fill, ImageFit::Stretch] {
+            let image = normalize(wide_image(), (60, 60), fit).to_rgba8();
+            assert_eq!(image.dimensions(), (60, 60));
+            assert_eq!(image.get_pixel(30, 2), &Rgba([255, 0, 0, 255]));
+        }
+    }
+
+    #[test]
+    fn test_scale() {
+        assert_eq!(ImageFit::Fit.scale((20.0, 10.0), (60, 60)), (3.0, 3.0));
+        assert_eq!(ImageFit::Fill.scale((20.0, 10.0), (60, 60)), (6.0, 6.0));
+        assert_eq!(ImageFit::Stretch.scale((20.0, 10.0), (60, 60)), (3.0, 6.0));
+    }
+}
@@ -1,10 +1,18 @@
 mod manifest;
 mod profile;
 mod image;
-
-pub use profile::{Profile, open_profiles};
-pub use manifest::{Manifest, EncoderActions, Action, Page, Button};
-pub use image::{ButtonImage, ButtonImageLoader, ImageError, ImageLoader, ImageCache};
+mod check;
+mod matcher;
+mod condition;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+pub use profile::{Profile, LoadedProfiles, open_profiles, open_profiles_partial};
+pub use manifest::{Manifest, EncoderActions, Action, Page, Button, ButtonKind, Toggle, ToggleState, Probe, LongPress, DoubleTap, SequenceStep, Navigation, NavigationCommand, LastPage, DeviceTarget, parse_kind};
+pub use image::{image_map_path, is_supported_image, parse_color, Align, GeneratedImage, ImageFit, DEFAULT_FONT, ButtonImage, ButtonImageLoader, Animation, Frame, KeyImage, ImageError, ImageLoader, ImageCache};
+pub use condition::{Condition, ConditionContext, TimeRange};
+pub use matcher::{AppMatch, AppPattern, ProfileMatcher};
+pub use check::{check_manifest, check_profile, check_profiles, Diagnostic, Severity};
 
 use thiserror::Error;
 
@@ -33,4 +41,13 @@ pub enum ProfileError {
     
     #[error("Manifest file not found at {0}")]
     ManifestFileNotFound(String),
+
+    #[error("Unknown device: {0}")]
+    UnknownDevice(String),
+
+    #[error("Extended profile not found: {0}")]
+    ExtendedProfileNotFound(String),
+
+    #[error("Profile inheritance cycle: {0}")]
+    InheritanceCycle(String),
 }
\ No newline at end of file
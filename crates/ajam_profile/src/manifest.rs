@@ -1,12 +1,15 @@
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use ajazz_sdk::info::Kind;
+use image::Rgba;
 
 use ajam_keypress::KeyCombo;
 
-use crate::image::ButtonImage;
+use crate::image::{parse_color, ButtonImage, ImageFit};
+use crate::condition::Condition;
+use crate::matcher::AppMatch;
 
 /// Action is an action that can be performed.
 #[derive(Debug, Clone, Deserialize)]
@@ -16,8 +19,125 @@ pub enum Action {
     Keys { keys: KeyCombo },
     /// Command is a command to run in the terminal.
     Command { command: String },
-    /// Navigate is a path to navigate to.
+    /// Navigate is a page to navigate to, or a `@next`, `@previous`, `@home` or `@back` command.
     Navigate { navigate: String },
+    /// Type is a text to type.
+    Type {
+        #[serde(rename = "type")]
+        text: String,
+        /// CharDelayMs is the pause between typed characters, in milliseconds.
+        #[serde(default)]
+        char_delay_ms: u64,
+    },
+    /// Sequence is a list of steps performed in order.
+    Sequence {
+        sequence: Vec<SequenceStep>,
+        /// Repeat is how many times the whole sequence runs.
+        #[serde(default = "default_repeat")]
+        repeat: u32,
+    },
+}
+
+/// SequenceStep is a single step of an action sequence.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum SequenceStep {
+    /// Delay is a pause before the next step.
+    Delay { delay_ms: u64 },
+    /// Action is an action to perform.
+    Action(Action),
+}
+
+fn default_repeat() -> u32 {
+    1
+}
+
+/// NavigationCommand is a built-in navigation, written as `@name` in place of a page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NavigationCommand {
+    Next,
+    Previous,
+    Home,
+    Back,
+}
+
+impl NavigationCommand {
+    /// Parses a navigate target, returns `None` for plain page names and unknown commands.
+    pub fn parse(target: &str) -> Option<Self> {
+        match target.strip_prefix('@')? {
+            "next" => Some(Self::Next),
+            "previous" => Some(Self::Previous),
+            "home" => Some(Self::Home),
+            "back" => Some(Self::Back),
+            _ => None,
+        }
+    }
+}
+
+/// Navigation assigns keys to the built-in navigation commands.
+///
+/// Without it, the first three keys without a display are previous, home and next.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Navigation {
+    /// Enabled turns the navigation keys off when false.
+    #[serde(default = "default_navigation_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub previous: Option<u8>,
+    #[serde(default)]
+    pub home: Option<u8>,
+    #[serde(default)]
+    pub next: Option<u8>,
+    #[serde(default)]
+    pub back: Option<u8>,
+}
+
+fn default_navigation_enabled() -> bool {
+    true
+}
+
+impl Navigation {
+    /// Returns the navigation keys of a device without configuration.
+    pub fn default_for(kind: Kind) -> Self {
+        let first = kind.display_key_count();
+        let key = |offset: u8| Some(first + offset).filter(|key| *key < kind.key_count());
+        Self {
+            enabled: true,
+            previous: key(0),
+            home: key(1),
+            next: key(2),
+            back: None,
+        }
+    }
+
+    /// Returns the command bound to the key.
+    pub fn command(&self, key: u8) -> Option<NavigationCommand> {
+        if !self.enabled {
+            return None;
+        }
+        [
+            (self.previous, NavigationCommand::Previous),
+            (self.home, NavigationCommand::Home),
+            (self.next, NavigationCommand::Next),
+            (self.back, NavigationCommand::Back),
+        ]
+        .into_iter()
+        .find_map(|(bound, command)| (bound == Some(key)).then_some(command))
+    }
+
+    /// Returns the bound keys along with their names.
+    pub fn keys(&self) -> Vec<(&'static str, u8)> {
+        [
+            ("previous", self.previous),
+            ("home", self.home),
+            ("next", self.next),
+            ("back", self.back),
+        ]
+        .into_iter()
+        .filter_map(|(name, key)| Some((name, key?)))
+        .collect()
+    }
 }
 
 /// EncoderActions is a set of actions for an encoder.
@@ -31,34 +151,231 @@ pub struct EncoderActions {
     pub click: Option<Action>,
 }
 
+pub const DEFAULT_LONG_PRESS_THRESHOLD_MS: u64 = 500;
+pub const DEFAULT_DOUBLE_TAP_INTERVAL_MS: u64 = 300;
+
+/// LongPress is an action performed when a button is held.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LongPress {
+    /// Action is the action to perform when the button is held long enough.
+    pub action: Action,
+    /// ThresholdMs is how long the button must be held, in milliseconds.
+    #[serde(default = "default_long_press_threshold")]
+    pub threshold_ms: u64,
+}
+
+/// DoubleTap is an action performed when a button is tapped twice.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DoubleTap {
+    /// Action is the action to perform on the second tap.
+    pub action: Action,
+    /// IntervalMs is the longest pause between the taps, in milliseconds.
+    #[serde(default = "default_double_tap_interval")]
+    pub interval_ms: u64,
+}
+
+fn default_long_press_threshold() -> u64 {
+    DEFAULT_LONG_PRESS_THRESHOLD_MS
+}
+
+fn default_double_tap_interval() -> u64 {
+    DEFAULT_DOUBLE_TAP_INTERVAL_MS
+}
+
+pub const DEFAULT_PROBE_INTERVAL_MS: u64 = 5000;
+
+/// ToggleState is the look and behavior of a toggle button in one of its states.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ToggleState {
+    /// Image is the image shown in this state, keys without a display have none.
+    #[serde(default)]
+    pub image: Option<ButtonImage>,
+    /// Action is the action to perform when the button is clicked in this state.
+    pub action: Action,
+}
+
+/// Probe is a shell command reporting the actual state of a toggle.
+///
+/// Zero exit status means the toggle is on.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Probe {
+    /// Command is the command to run in the terminal.
+    pub command: String,
+    /// IntervalMs is the pause between probes, in milliseconds.
+    #[serde(default = "default_probe_interval")]
+    pub interval_ms: u64,
+}
+
+fn default_probe_interval() -> u64 {
+    DEFAULT_PROBE_INTERVAL_MS
+}
+
+/// Toggle is a button switching between on and off states.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Toggle {
+    pub on: ToggleState,
+    pub off: ToggleState,
+    /// Persist keeps the state across restarts.
+    #[serde(default)]
+    pub persist: bool,
+    /// Probe updates the state from the system.
+    #[serde(default)]
+    pub probe: Option<Probe>,
+}
+
+/// ButtonKind is what a button shows and does when clicked.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ButtonKind {
+    /// Toggle is a button with on and off states.
+    Toggle { toggle: Toggle },
+    /// Static is a button with a single image and action.
+    Static { image: ButtonImage, action: Action },
+    /// Plain is a button without an image, e.g. a key without a display.
+    Plain { action: Action },
+}
+
 /// Button is a screen button config.
 #[derive(Debug, Clone, Deserialize)]
 pub struct Button {
-    /// Image is the image for the button.
-    pub image: ButtonImage,
-    /// Action is the action to perform when the button is clicked.
-    pub action: Action,
+    /// Kind is the image and action of the button.
+    #[serde(flatten)]
+    pub kind: ButtonKind,
+    /// LongPress is the action to perform when the button is held.
+    #[serde(default)]
+    pub long_press: Option<LongPress>,
+    /// DoubleTap is the action to perform when the button is tapped twice.
+    #[serde(default)]
+    pub double_tap: Option<DoubleTap>,
+    /// Fit overrides how the profile scales the button images.
+    #[serde(default)]
+    pub fit: Option<ImageFit>,
+    /// Origin is the directory of the extended profile the button is inherited from.
+    #[serde(skip)]
+    pub origin: Option<PathBuf>,
+}
+
+impl Button {
+    /// Returns the image to show, `on` is ignored by static buttons.
+    pub fn image(&self, on: bool) -> Option<&ButtonImage> {
+        match &self.kind {
+            ButtonKind::Static { image, .. } => Some(image),
+            ButtonKind::Toggle { toggle } => toggle.state(on).image.as_ref(),
+            ButtonKind::Plain { .. } => None,
+        }
+    }
+
+    /// Returns the action to perform on click, `on` is ignored by static buttons.
+    pub fn action(&self, on: bool) -> &Action {
+        match &self.kind {
+            ButtonKind::Static { action, .. } | ButtonKind::Plain { action } => action,
+            ButtonKind::Toggle { toggle } => &toggle.state(on).action,
+        }
+    }
+
+    pub fn toggle(&self) -> Option<&Toggle> {
+        match &self.kind {
+            ButtonKind::Toggle { toggle } => Some(toggle),
+            ButtonKind::Static { .. } | ButtonKind::Plain { .. } => None,
+        }
+    }
+
+    /// Returns true if the button has long press or double tap actions.
+    pub fn has_gestures(&self) -> bool {
+        self.long_press.is_some() || self.double_tap.is_some()
+    }
+}
+
+impl Toggle {
+    pub fn state(&self, on: bool) -> &ToggleState {
+        if on {
+            &self.on
+        } else {
+            &self.off
+        }
+    }
+}
+
+/// LastPage is how a profile restores the page last shown in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LastPage {
+    /// Remember shows the last page when the app is focused again, instead of the main page.
+    #[serde(default = "default_remember_last_page")]
+    pub remember: bool,
+    /// Persist keeps the last page across restarts.
+    #[serde(default)]
+    pub persist: bool,
+}
+
+fn default_remember_last_page() -> bool {
+    true
+}
+
+impl Default for LastPage {
+    fn default() -> Self {
+        Self {
+            remember: default_remember_last_page(),
+            persist: false,
+        }
+    }
 }
 
 /// Page is a page in the manifest.
 #[derive(Debug, Deserialize, Default, Clone)]
 pub struct Page {
-    /// Buttons is a map of button index char to button configs.
+    /// When is the condition the page is shown automatically while it holds.
+    #[serde(default)]
+    pub when: Option<Condition>,
+    /// Buttons is a map of button index to button configs.
+    ///
+    /// Keys are strings, as flattened maps can't be keyed by numbers.
     #[serde(flatten)]
-    pub buttons: HashMap<char, Button>,
+    pub buttons: HashMap<String, Button>,
 }
 
 /// Manifest is the manifest for a profile.
+///
+/// Pages configure every key of the device, with or without a display, and encoders its knobs.
+/// Touch strips and secondary displays are not supported, the device API neither draws on
+/// them nor reports their input.
 #[derive(Debug, Deserialize, Clone)]
 pub struct Manifest {
     /// PagesOrder is the order of the pages.
     #[serde(default)]
     pub pages_order: Vec<String>,
-    /// Device is the device type.
+    /// Extends is the name of the profile to inherit pages and encoders from.
+    #[serde(default)]
+    pub extends: Option<String>,
+    /// AppMatch is the apps and windows the profile is shown for, besides the one named like it.
+    #[serde(default, rename = "match")]
+    pub app_match: AppMatch,
+    /// When is the condition the profile is matched only while it holds.
+    #[serde(default)]
+    pub when: Option<Condition>,
+    /// Device is the device type, inherited from the extended profile if omitted.
+    /// It may be followed by a serial number to target a single deck, e.g. `akp153:A1B2C3`.
+    #[serde(default)]
     pub device: String,
+    /// Background is the colour transparent images are composited onto,
+    /// inherited from the extended profile if omitted.
+    #[serde(default)]
+    pub background: Option<String>,
+    /// Fit is how images are scaled to the key size.
+    #[serde(default)]
+    pub fit: ImageFit,
+    /// Navigation overrides the navigation keys, the common profile sets them for every profile.
+    /// It is inherited from the extended profile if omitted.
+    #[serde(default)]
+    pub navigation: Option<Navigation>,
+    /// LastPage is how the page last shown in the profile is restored.
+    #[serde(default)]
+    pub last_page: LastPage,
     /// Pages is a map of page names to pages.
+    #[serde(default)]
     pub pages: HashMap<String, Page>,
     /// Encoders is a map of encoder index char to encoder actions.
+    #[serde(default)]
     pub encoders: HashMap<char, EncoderActions>,
 }
 
@@ -74,6 +391,53 @@ impl Manifest {
         self.encoders.get(&ch)
     }
 
+    /// Returns the background colour, black if it is not set or invalid.
+    pub fn background_color(&self) -> Rgba<u8> {
+        self.background
+            .as_deref()
+            .and_then(parse_color)
+            .unwrap_or(Rgba([0, 0, 0, 255]))
+    }
+
+    /// Fills what the manifest leaves out from the manifest it extends.
+    ///
+    /// Pages, their buttons and encoders are merged key by key, the manifest's own ones win.
+    /// Buttons declared by the parent load their images from `origin`, its directory, and
+    /// keep its fit unless they set their own. The device, background and navigation are
+    /// inherited when omitted. The fit and last page always have a value, they are not.
+    pub fn inherit(&mut self, parent: &Manifest, origin: &Path) {
+        if self.device.is_empty() {
+            self.device = parent.device.clone();
+        }
+        if self.background.is_none() {
+            self.background = parent.background.clone();
+        }
+        if self.navigation.is_none() {
+            self.navigation = parent.navigation.clone();
+        }
+        if self.pages_order.is_empty() {
+            self.pages_order = parent.pages_order.clone();
+        }
+        for (page_name, parent_page) in parent.pages.iter() {
+            let page = self.pages.entry(page_name.clone()).or_default();
+            if page.when.is_none() {
+                page.when = parent_page.when.clone();
+            }
+            for (index, button) in parent_page.buttons.iter() {
+                if page.buttons.contains_key(index) {
+                    continue;
+                }
+                let mut button = button.clone();
+                button.origin.get_or_insert_with(|| origin.to_path_buf());
+                button.fit.get_or_insert(parent.fit);
+                page.buttons.insert(index.clone(), button);
+            }
+        }
+        for (index, actions) in parent.encoders.iter() {
+            self.encoders.entry(*index).or_insert_with(|| actions.clone());
+        }
+    }
+
     pub fn get_page(&self, name: &str) -> Option<&Page> {
         self.pages.get(name)
     }
@@ -83,29 +447,80 @@ impl Manifest {
     }
 
     pub fn kind(&self) -> Kind {
-        match self.device.as_str() {
-            "akp03" => Kind::Akp03,
-            "akp03e" => Kind::Akp03E,
-            "akp03r" => Kind::Akp03R,
-            "akp03r_rev2" => Kind::Akp03RRev2,
-            "akp153" => Kind::Akp153,
-            "akp153e" => Kind::Akp153E,
-            "akp153r" => Kind::Akp153R,
-            _ => panic!("Unknown device: {}", self.device),
+        match DeviceTarget::parse(&self.device) {
+            Some(target) => target.kind,
+            None => panic!("Unknown device: {}", self.device),
         }
     }
+
+    /// Returns whether the profile is made for the deck.
+    pub fn targets(&self, kind: Kind, serial: &str) -> bool {
+        DeviceTarget::parse(&self.device).is_some_and(|target| target.matches(kind, serial))
+    }
+}
+
+/// DeviceTarget is a parsed manifest `device` field, the decks a profile is made for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceTarget {
+    pub kind: Kind,
+    /// Serial is the serial number of the only deck the profile is shown on.
+    pub serial: Option<String>,
+}
+
+impl DeviceTarget {
+    /// Parses a device name optionally followed by a colon and a serial number.
+    pub fn parse(device: &str) -> Option<Self> {
+        let (kind, serial) = match device.split_once(':') {
+            Some((_, "")) => return None,
+            Some((kind, serial)) => (kind, Some(serial.to_string())),
+            None => (device, None),
+        };
+        Some(Self {
+            kind: parse_kind(kind)?,
+            serial,
+        })
+    }
+
+    /// Returns whether the deck is targeted. Kinds with the same key layout,
+    /// e.g. `akp03` and `akp03r`, are interchangeable.
+    pub fn matches(&self, kind: Kind, serial: &str) -> bool {
+        let same_layout = self.kind == kind
+            || (self.kind.key_count() == kind.key_count()
+                && self.kind.display_key_count() == kind.display_key_count());
+        same_layout && self.serial.as_deref().map_or(true, |target| target == serial)
+    }
+}
+
+/// Parses a device name as used in the manifest `device` field.
+pub fn parse_kind(name: &str) -> Option<Kind> {
+    match name {
+        "akp03" => Some(Kind::Akp03),
+        "akp03e" => Some(Kind::Akp03E),
+        "akp03r" => Some(Kind::Akp03R),
+        "akp03r_rev2" => Some(Kind::Akp03RRev2),
+        "akp153" => Some(Kind::Akp153),
+        "akp153e" => Some(Kind::Akp153E),
+        "akp153r" => Some(Kind::Akp153R),
+        _ => None,
+    }
 }
 
 impl Page {
     pub fn get_button(&self, index: u8) -> Option<&Button> {
-        let ch = char::from_digit(index as u32, 10)?;
-        self.buttons.get(&ch)
+        self.buttons.get(&index.to_string())
+    }
+
+    /// Returns the buttons with valid indexes.
+    pub fn indexed_buttons(&self) -> impl Iterator<Item = (u8, &Button)> {
+        self.buttons
+            .iter()
+            .filter_map(|(index, button)| Some((index.parse().ok()?, button)))
     }
 
     pub fn iter_buttons(&self, count: usize) -> impl Iterator<Item = Option<&Button>> {
         let mut buttons: Vec<Option<&Button>> = vec![None; count];
-        for (index, button) in self.buttons.iter() {
-            let index = index.to_digit(10).unwrap() as usize;
+        for (index, button) in self.indexed_buttons() {
+            let index = index as usize;
             if index >= buttons.len() {
                 continue;
             }
@@ -123,19 +538,33 @@ mod tests {
     #[test]
     fn test_get_button() {
         let mut manifest = Manifest {
+            extends: None,
+            app_match: AppMatch::default(),
+            when: None,
             device: "akp03".to_string(),
+            background: None,
+            fit: ImageFit::Fit,
+            navigation: None,
+            last_page: LastPage::default(),
             pages_order: vec!["test".to_string()],
             pages: HashMap::new(),
             encoders: HashMap::new(),
         };
 
         let mut page = Page {
+            when: None,
             buttons: HashMap::new(),
         };
 
-        page.buttons.insert('0', Button {
-            image: ButtonImage::Source { src: "test.png".to_string() },
-            action: Action::Command { command: "echo 'test'".to_string() },
+        page.buttons.insert("0".to_string(), Button {
+            kind: ButtonKind::Static {
+                image: ButtonImage::Source { src: "test.png".to_string() },
+                action: Action::Command { command: "echo 'test'".to_string() },
+            },
+            long_press: None,
+            double_tap: None,
+            fit: None,
+            origin: None,
         });
 
         manifest.pages.insert("test".to_string(), page);
@@ -147,7 +576,14 @@ mod tests {
     #[test]
     fn test_kind() {
         let manifest = Manifest {
+            extends: None,
+            app_match: AppMatch::default(),
+            when: None,
             device: "akp03".to_string(),
+            background: None,
+            fit: ImageFit::Fit,
+            navigation: None,
+            last_page: LastPage::default(),
             pages_order: vec!["test".to_string()],
             pages: HashMap::new(),
             encoders: HashMap::new(),
@@ -155,4 +591,227 @@ mod tests {
 
         assert_eq!(manifest.kind(), Kind::Akp03);
     }
+
+    #[test]
+    fn test_device_target() {
+        let target = DeviceTarget::parse("akp153:A1B2C3").unwrap();
+        assert_eq!(target.kind, Kind::Akp153);
+        assert!(target.matches(Kind::Akp153, "A1B2C3"));
+        assert!(!target.matches(Kind::Akp153, "D4E5F6"));
+
+        let target = DeviceTarget::parse("akp03").unwrap();
+        assert!(target.matches(Kind::Akp03R, "D4E5F6"));
+        assert!(!target.matches(Kind::Akp153, "D4E5F6"));
+
+        assert!(DeviceTarget::parse("akp03:").is_none());
+        assert!(DeviceTarget::parse("akp999:A1B2C3").is_none());
+    }
+
+    #[test]
+    fn test_button_gestures() {
+        let button: Button = serde_yaml::from_str(
+            r#"
+image:
+  src: a.png
+action:
+  command: "true"
+long_press:
+  action:
+    navigate: second
+double_tap:
+  action:
+    keys: cmd+c
+  interval_ms: 200
+"#,
+        )
+        .unwrap();
+
+        assert!(button.has_gestures());
+        assert_eq!(button.long_press.unwrap().threshold_ms, DEFAULT_LONG_PRESS_THRESHOLD_MS);
+        assert_eq!(button.double_tap.unwrap().interval_ms, 200);
+    }
+
+    #[test]
+    fn test_button_fit() {
+        let button: Button =
+            serde_yaml::from_str("image:\n  src: a.png\naction:\n  command: \"true\"\nfit: fill")
+                .unwrap();
+        assert_eq!(button.fit, Some(ImageFit::Fill));
+
+        let button: Button = serde_yaml::from_str("image:\n  src: a.png\naction:\n  command: \"true\"")
+            .unwrap();
+        assert_eq!(button.fit, None);
+    }
+
+    #[test]
+    fn test_toggle() {
+        let button: Button = serde_yaml::from_str(
+            r#"
+toggle:
+  on:
+    image:
+      src: on.png
+    action:
+      command: "disable"
+  off:
+    image:
+      src: off.png
+    action:
+      command: "enable"
+  persist: true
+  probe:
+    command: "check"
+"#,
+        )
+        .unwrap();
+
+        let toggle = button.toggle().unwrap();
+        assert!(toggle.persist);
+        assert_eq!(toggle.probe.as_ref().unwrap().interval_ms, DEFAULT_PROBE_INTERVAL_MS);
+        assert!(matches!(button.image(true), Some(ButtonImage::Source { src }) if src == "on.png"));
+        assert!(matches!(button.action(false), Action::Command { command } if command == "enable"));
+
+        let button: Button =
+            serde_yaml::from_str("image:\n  src: a.png\naction:\n  navigate: main").unwrap();
+        assert!(button.toggle().is_none());
+        assert!(matches!(button.image(true), Some(ButtonImage::Source { src }) if src == "a.png"));
+    }
+
+    #[test]
+    fn test_buttons_without_display() {
+        let page: Page = serde_yaml::from_str(
+            r#"
+12:
+  image:
+    src: a.png
+  action:
+    navigate: main
+16:
+  action:
+    keys: cmd+c
+"#,
+        )
+        .unwrap();
+
+        assert!(page.get_button(12).unwrap().image(false).is_some());
+        let button = page.get_button(16).unwrap();
+        assert!(button.image(false).is_none());
+        assert!(matches!(button.action(false), Action::Keys { .. }));
+        assert_eq!(page.iter_buttons(15).flatten().count(), 1);
+    }
+
+    #[test]
+    fn test_navigation() {
+        assert_eq!(NavigationCommand::parse("@back"), Some(NavigationCommand::Back));
+        assert_eq!(NavigationCommand::parse("main"), None);
+        assert_eq!(NavigationCommand::parse("@nope"), None);
+
+        let navigation = Navigation::default_for(Kind::Akp03);
+        assert_eq!(navigation.command(6), Some(NavigationCommand::Previous));
+        assert_eq!(navigation.command(8), Some(NavigationCommand::Next));
+        assert_eq!(navigation.command(0), None);
+
+        let navigation: Navigation = serde_yaml::from_str("back: 14\nnext: 13").unwrap();
+        assert_eq!(navigation.command(14), Some(NavigationCommand::Back));
+        assert_eq!(navigation.command(6), None);
+        assert_eq!(navigation.keys(), vec![("next", 13), ("back", 14)]);
+
+        let navigation: Navigation = serde_yaml::from_str("enabled: false\nnext: 13").unwrap();
+        assert_eq!(navigation.command(13), None);
+    }
+
+    #[test]
+    fn test_inherit() {
+        let parent: Manifest = serde_yaml::from_str(
+            r##"
+device: akp03
+background: "#1e1e2e"
+fit: fill
+navigation: { next: 5 }
+last_page: { remember: false }
+pages_order: [main, media]
+pages:
+  main:
+    0: { image: { src: a.png }, action: { navigate: media } }
+    1: { image: { src: b.png }, action: { command: "true" } }
+  media: {}
+encoders:
+  0: { plus: { keys: volume_up }, minus: { keys: volume_down } }
+"##,
+        )
+        .unwrap();
+        let mut manifest: Manifest = serde_yaml::from_str(
+            r#"
+extends: common
+pages:
+  main:
+    1: { image: { src: c.png }, action: { command: "false" } }
+"#,
+        )
+        .unwrap();
+
+        manifest.inherit(&parent, Path::new("profiles/common"));
+
+        assert_eq!(manifest.kind(), Kind::Akp03);
+        assert_eq!(manifest.pages_order, vec!["main", "media"]);
+        assert!(manifest.get_page("media").is_some());
+        assert!(manifest.get_encoder_actions(0).is_some());
+        let page = manifest.get_page("main").unwrap();
+        let inherited = page.get_button(0).unwrap();
+        assert_eq!(inherited.origin.as_deref(), Some(Path::new("profiles/common")));
+        assert_eq!(inherited.fit, Some(ImageFit::Fill));
+        let own = page.get_button(1).unwrap();
+        assert!(own.origin.is_none());
+        assert_eq!(own.fit, None);
+        assert_eq!(manifest.background.as_deref(), Some("#1e1e2e"));
+        assert!(manifest.navigation.is_some());
+        assert_eq!(manifest.fit, ImageFit::default());
+        assert!(manifest.last_page.remember);
+        assert!(matches!(own.action(false), Action::Command { command } if command == "false"));
+    }
+
+    #[test]
+    fn test_last_page() {
+        let last_page: LastPage = serde_yaml::from_str("persist: true").unwrap();
+        assert_eq!(last_page, LastPage { remember: true, persist: true });
+
+        let last_page: LastPage = serde_yaml::from_str("remember: false").unwrap();
+        assert!(!last_page.remember);
+        assert!(serde_yaml::from_str::<LastPage>("forget: true").is_err());
+    }
+
+    #[test]
+    fn test_type() {
+        let action: Action = serde_yaml::from_str("type: \"Привет 👋\"\nchar_delay_ms: 20").unwrap();
+
+        let Action::Type { text, char_delay_ms } = action else {
+            panic!("expected a type action");
+        };
+        assert_eq!(text, "Привет 👋");
+        assert_eq!(char_delay_ms, 20);
+    }
+
+    #[test]
+    fn test_sequence() {
+        let action: Action = serde_yaml::from_str(
+            r#"
+sequence:
+  - keys: cmd+s
+  - delay_ms: 200
+  - command: "echo saved"
+  - navigate: main
+repeat: 2
+"#,
+        )
+        .unwrap();
+
+        let Action::Sequence { sequence, repeat } = action else {
+            panic!("expected a sequence");
+        };
+        assert_eq!(repeat, 2);
+        assert_eq!(sequence.len(), 4);
+        assert!(matches!(sequence[0], SequenceStep::Action(Action::Keys { .. })));
+        assert!(matches!(sequence[1], SequenceStep::Delay { delay_ms: 200 }));
+        assert!(matches!(sequence[3], SequenceStep::Action(Action::Navigate { .. })));
+    }
 }
@@ -0,0 +1,251 @@
+use std::cmp::Reverse;
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::condition::{Condition, ConditionContext};
+use crate::profile::Profile;
+
+/// AppMatch is the `match` section of a manifest, the apps and windows the profile is shown for.
+///
+/// The profile directory name always matches the bundle id it is equal to.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AppMatch {
+    /// Apps are bundle ids. `*` and `?` wildcards are supported,
+    /// a pattern enclosed in slashes is a regular expression.
+    #[serde(default)]
+    pub apps: Vec<String>,
+    /// Title is a regular expression the title of the focused window must match.
+    #[serde(default)]
+    pub title: Option<String>,
+}
+
+/// AppPattern is a parsed entry of [`AppMatch::apps`].
+#[derive(Debug, Clone)]
+pub enum AppPattern {
+    Exact(String),
+    Wildcard(String),
+    Regex(Regex),
+}
+
+impl AppPattern {
+    pub fn parse(pattern: &str) -> Result<Self, regex::Error> {
+        if let Some(regex) = pattern
+            .strip_prefix('/')
+            .and_then(|pattern| pattern.strip_suffix('/'))
+        {
+            return Ok(AppPattern::Regex(Regex::new(regex)?));
+        }
+        if pattern.contains(['*', '?']) {
+            return Ok(AppPattern::Wildcard(pattern.to_string()));
+        }
+        Ok(AppPattern::Exact(pattern.to_string()))
+    }
+
+    pub fn matches(&self, bundle_id: &str) -> bool {
+        match self {
+            AppPattern::Exact(exact) => exact == bundle_id,
+            AppPattern::Wildcard(pattern) => wildcard_match(pattern, bundle_id),
+            AppPattern::Regex(regex) => regex.is_match(bundle_id),
+        }
+    }
+
+    /// Rank orders the patterns from the least to the most specific.
+    fn rank(&self) -> u8 {
+        match self {
+            AppPattern::Regex(_) => 1,
+            AppPattern::Wildcard(_) => 2,
+            AppPattern::Exact(_) => 3,
+        }
+    }
+}
+
+/// Matches `*` against any run of characters and `?` against a single one.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[derive(Debug)]
+struct Rule {
+    profile: String,
+    apps: Vec<AppPattern>,
+    title: Option<Regex>,
+    when: Option<Condition>,
+}
+
+/// ProfileMatcher picks the profile for the focused app and window.
+///
+/// Profiles with a `when` condition only match while it holds. When several
+/// profiles match, the one with a matching `title` wins, then the one with a
+/// condition, then exact bundle ids win over wildcards and wildcards over
+/// regular expressions. Remaining ties go to the first profile by name.
+#[derive(Debug)]
+pub struct ProfileMatcher {
+    rules: Vec<Rule>,
+}
+
+impl ProfileMatcher {
+    /// Creates a matcher for the profiles, invalid patterns are skipped.
+    pub fn new<'a>(profiles: impl IntoIterator<Item = &'a Profile>) -> Self {
+        let mut rules: Vec<Rule> = profiles
+            .into_iter()
+            .filter_map(|profile| {
+                let app_match = &profile.manifest.app_match;
+                let title = match app_match.title.as_deref().map(Regex::new) {
+                    Some(Ok(title)) => Some(title),
+                    Some(Err(_)) => return None,
+                    None => None,
+                };
+                let mut apps = vec![AppPattern::Exact(profile.name.clone())];
+                apps.extend(app_match.apps.iter().filter_map(|app| AppPattern::parse(app).ok()));
+                Some(Rule {
+                    profile: profile.name.clone(),
+                    apps,
+                    title,
+                    when: profile.manifest.when.clone(),
+                })
+            })
+            .collect();
+        rules.sort_by(|a, b| a.profile.cmp(&b.profile));
+        Self { rules }
+    }
+
+    /// Returns the name of the profile for the app, `title` is the title of its focused window.
+    pub fn find(
+        &self,
+        bundle_id: &str,
+        title: Option<&str>,
+        context: &ConditionContext,
+    ) -> Option<&str> {
+        self.find_where(bundle_id, title, context, |_| true)
+    }
+
+    /// Same as [`ProfileMatcher::find`], only considering the profiles `accept` returns true for.
+    pub fn find_where(
+        &self,
+        bundle_id: &str,
+        title: Option<&str>,
+        context: &ConditionContext,
+        accept: impl Fn(&str) -> bool,
+    ) -> Option<&str> {
+        self.rules
+            .iter()
+            .filter(|rule| accept(&rule.profile))
+            .filter_map(|rule| {
+                let has_when = match &rule.when {
+                    Some(when) if when.evaluate(context) => true,
+                    Some(_) => return None,
+                    None => false,
+                };
+                let has_title = match (&rule.title, title) {
+                    (Some(pattern), Some(title)) if pattern.is_match(title) => true,
+                    (Some(_), _) => return None,
+                    (None, _) => false,
+                };
+                let rank = rule
+                    .apps
+                    .iter()
+                    .filter(|app| app.matches(bundle_id))
+                    .map(AppPattern::rank)
+                    .max()?;
+                Some((rule, (has_title, has_when, rank)))
+            })
+            .min_by_key(|(_, specificity)| Reverse(*specificity))
+            .map(|(rule, _)| rule.profile.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("com.jetbrains.*", "com.jetbrains.intellij"));
+        assert!(wildcard_match("com.?pple.*", "com.apple.Safari"));
+        assert!(wildcard_match("*.Terminal", "com.apple.Terminal"));
+        assert!(!wildcard_match("com.jetbrains.*", "com.jetbrains"));
+        assert!(!wildcard_match("com.apple.*.beta", "com.apple.Safari"));
+    }
+
+    #[test]
+    fn test_app_pattern() {
+        assert!(matches!(AppPattern::parse("com.apple.Safari"), Ok(AppPattern::Exact(_))));
+        assert!(matches!(AppPattern::parse("com.apple.*"), Ok(AppPattern::Wildcard(_))));
+        let regex = AppPattern::parse(r"/^com\.(google|microsoft)\./").unwrap();
+        assert!(regex.matches("com.google.Chrome"));
+        assert!(!regex.matches("org.mozilla.firefox"));
+        assert!(AppPattern::parse("/(/").is_err());
+    }
+
+    #[test]
+    fn test_precedence() {
+        let dir = TempDir::new();
+        let profiles = [
+            ("com.jetbrains.intellij", ""),
+            ("jetbrains", "match:\n  apps: [\"com.jetbrains.*\"]\n"),
+            ("ajam-project", "match:\n  apps: [\"/^com\\\\.jetbrains\\\\./\"]\n  title: \"^ajam \"\n"),
+            ("editors", "match:\n  apps: [\"/^com\\\\.(jetbrains|microsoft)\\\\./\"]\n"),
+            ("meeting", "match:\n  apps: [\"*\"]\nwhen:\n  command: pgrep zoom\n"),
+        ];
+        for (name, app_match) in profiles {
+            let manifest = format!("{}device: akp03\npages: {{}}\nencoders: {{}}\n", app_match);
+            dir.write(&format!("{}/manifest.yaml", name), manifest);
+        }
+        let profiles = crate::open_profiles(dir.path()).unwrap();
+        let matcher = ProfileMatcher::new(profiles.values());
+        let context = ConditionContext::default();
+
+        assert_eq!(
+            matcher.find("com.jetbrains.intellij", None, &context),
+            Some("com.jetbrains.intellij")
+        );
+        assert_eq!(matcher.find("com.jetbrains.goland", None, &context), Some("jetbrains"));
+        assert_eq!(
+            matcher.find("com.microsoft.VSCode", Some("ajam – main.rs"), &context),
+            Some("editors")
+        );
+        assert_eq!(
+            matcher.find("com.jetbrains.intellij", Some("ajam – main.rs"), &context),
+            Some("ajam-project")
+        );
+        assert_eq!(matcher.find("com.apple.Safari", None, &context), None);
+        assert_eq!(
+            matcher.find_where("com.jetbrains.goland", None, &context, |name| name != "jetbrains"),
+            Some("editors")
+        );
+
+        let mut context = ConditionContext::default();
+        context.commands.insert("pgrep zoom".to_string(), true);
+        assert_eq!(matcher.find("com.apple.Safari", None, &context), Some("meeting"));
+        assert_eq!(matcher.find("com.jetbrains.goland", None, &context), Some("meeting"));
+    }
+}
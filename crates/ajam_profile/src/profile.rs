@@ -2,10 +2,10 @@ use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
 use crate::image::{ButtonImageLoader, ImageCache};
-use crate::manifest::Manifest;
+use crate::manifest::{DeviceTarget, Manifest};
 use crate::ProfileError;
 
-const MANIFEST_FILE_NAME: &str = "manifest.yaml";
+pub(crate) const MANIFEST_FILE_NAME: &str = "manifest.yaml";
 
 #[derive(Debug, Clone)]
 pub struct Profile {
@@ -17,14 +17,84 @@ pub struct Profile {
 impl Profile {
     pub fn from_dir(path: PathBuf) -> Result<Self, ProfileError> {
         let manifest = Manifest::from_file(path.join(MANIFEST_FILE_NAME))?;
+        // A profile extending another one may inherit its device.
+        let inherits_device = manifest.device.is_empty() && manifest.extends.is_some();
+        if !inherits_device && DeviceTarget::parse(&manifest.device).is_none() {
+            return Err(ProfileError::UnknownDevice(manifest.device));
+        }
         let name = path.file_name().unwrap().to_str().unwrap().to_string();
 
         Ok(Self { name, manifest, path })
     }
 
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
     pub fn get_loader<'a>(&'a self, cache: &'a mut ImageCache) -> ButtonImageLoader<'a> {
-        ButtonImageLoader::new(cache, self.path.clone())
+        let (width, height) = self.manifest.kind().key_image_format().size;
+        ButtonImageLoader::new(cache, self.path.clone(), (width as u32, height as u32))
+            .with_background(self.manifest.background_color())
+            .with_fit(self.manifest.fit)
+    }
+}
+
+/// Applies the chain of `extends` to a manifest, `lookup` returns a profile manifest and
+/// directory by name.
+pub(crate) fn resolve_extends<F>(
+    name: &str,
+    manifest: &Manifest,
+    mut lookup: F,
+) -> Result<Manifest, ProfileError>
+where
+    F: FnMut(&str) -> Result<(Manifest, PathBuf), ProfileError>,
+{
+    let mut names = vec![name.to_string()];
+    let mut parents = Vec::new();
+    let mut extends = manifest.extends.clone();
+    while let Some(parent_name) = extends {
+        let cycle = names.contains(&parent_name);
+        names.push(parent_name.clone());
+        if cycle {
+            return Err(ProfileError::InheritanceCycle(names.join(" -> ")));
+        }
+        let (parent, path) = lookup(&parent_name)?;
+        extends = parent.extends.clone();
+        parents.push((parent, path));
+    }
+
+    let mut resolved = manifest.clone();
+    let mut parents = parents.into_iter().rev();
+    let Some((mut inherited, mut origin)) = parents.next() else {
+        return Ok(resolved);
+    };
+    for (mut parent, path) in parents {
+        parent.inherit(&inherited, &origin);
+        inherited = parent;
+        origin = path;
+    }
+    resolved.inherit(&inherited, &origin);
+    Ok(resolved)
+}
+
+/// Resolves `extends` of the profiles and returns the ones that failed by name.
+fn resolve_profiles(profiles: &mut HashMap<String, Profile>) -> Vec<(String, ProfileError)> {
+    let declared = profiles.clone();
+    let mut errors = Vec::new();
+    for (name, profile) in profiles.iter_mut() {
+        let resolved = resolve_extends(name, &profile.manifest, |parent| match declared.get(parent) {
+            Some(parent) => Ok((parent.manifest.clone(), parent.path.clone())),
+            None => Err(ProfileError::ExtendedProfileNotFound(parent.to_string())),
+        });
+        match resolved {
+            Ok(manifest) => profile.manifest = manifest,
+            Err(e) => errors.push((name.clone(), e)),
+        }
+    }
+    for (name, _) in errors.iter() {
+        profiles.remove(name);
     }
+    errors
 }
 
 pub fn open_profiles<P: AsRef<Path>>(dir: P) -> Result<HashMap<String, Profile>, ProfileError> {
@@ -38,5 +108,43 @@ pub fn open_profiles<P: AsRef<Path>>(dir: P) -> Result<HashMap<String, Profile>,
             profiles.insert(profile.name.clone(), profile);
         }
     }
+    if let Some((_, e)) = resolve_profiles(&mut profiles).into_iter().next() {
+        return Err(e);
+    }
     Ok(profiles)
 }
+
+/// LoadedProfiles is a profiles directory opened with [`open_profiles_partial`].
+#[derive(Debug)]
+pub struct LoadedProfiles {
+    pub profiles: HashMap<String, Profile>,
+    /// Errors of profiles that failed to load, by profile directory.
+    pub errors: Vec<(PathBuf, ProfileError)>,
+}
+
+/// Opens every profile in the directory.
+///
+/// Unlike [`open_profiles`], a broken profile does not fail the whole directory.
+pub fn open_profiles_partial<P: AsRef<Path>>(dir: P) -> Result<LoadedProfiles, ProfileError> {
+    let dir = dir.as_ref();
+    let mut loaded = LoadedProfiles {
+        profiles: HashMap::new(),
+        errors: Vec::new(),
+    };
+    for entry in dir.read_dir()? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.is_dir() {
+            match Profile::from_dir(path.clone()) {
+                Ok(profile) => {
+                    loaded.profiles.insert(profile.name.clone(), profile);
+                }
+                Err(e) => loaded.errors.push((path, e)),
+            }
+        }
+    }
+    for (name, e) in resolve_profiles(&mut loaded.profiles) {
+        loaded.errors.push((dir.join(name), e));
+    }
+    Ok(loaded)
+}